@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use gtk::glib::ExitCode;
+
+use crate::colorscheme::find_color_scheme;
+use crate::fourier::{Period, fft::DEFAULT_WINDOW_PERIOD};
+use crate::frequency_scale::{self, FrequencyScale};
+use crate::offline_renderer::{average_spectrum, render_file, scaled_spectrum, AudioClip, RenderOptions};
+use crate::sources::{AudioInputError, AudioSource, ChannelLayout};
+use crate::sources::network_source::{NetworkProtocol, NetworkSource};
+use crate::sources::pipe_source::{PipeInput, PipeSource, SampleFormat};
+
+/// A live scrolling spectrogram.
+///
+/// Without a subcommand, the graphical interface is started.
+/// The subcommands work on audio files, and don't need a display.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub input: InputArgs,
+}
+
+/// Options for visualizing raw PCM from another program or machine, instead of an audio device
+#[derive(Args)]
+pub struct InputArgs {
+    /// Read raw PCM from standard input
+    #[arg(long, conflicts_with_all = ["pipe", "listen"])]
+    stdin: bool,
+    /// Read raw PCM from a named pipe
+    #[arg(long, value_name = "PATH", conflicts_with = "listen")]
+    pipe: Option<PathBuf>,
+    /// Receive RTP (udp://ADDRESS:PORT) or a raw PCM stream (tcp://ADDRESS:PORT) from the network
+    #[arg(long, value_name = "URL", value_parser = parse_listen_address)]
+    listen: Option<(NetworkProtocol, SocketAddr)>,
+    /// Sample rate of the raw PCM, in Hz
+    #[arg(long, default_value_t = 48000)]
+    rate: u32,
+    /// Encoding of the raw PCM samples; RTP's L16 and L24 are s16be and s24be
+    #[arg(long, value_enum, default_value_t = SampleFormat::S16le)]
+    format: SampleFormat,
+    /// Number of interleaved channels in the raw PCM
+    #[arg(long, default_value_t = 2)]
+    channels: u16,
+}
+
+impl InputArgs {
+    /// The source requested on the command line, if any
+    pub fn source(&self) -> Result<Option<Box<dyn AudioSource>>, AudioInputError> {
+        let channels = ChannelLayout::from_count(self.channels)?;
+        if let Some((protocol, address)) = self.listen {
+            return Ok(Some(Box::new(NetworkSource::new(protocol, address, self.format, self.rate, channels))));
+        }
+        let input = match (&self.pipe, self.stdin) {
+            (Some(path), _) => PipeInput::Fifo(path.clone()),
+            (None, true) => PipeInput::Stdin,
+            (None, false) => return Ok(None),
+        };
+        Ok(Some(Box::new(PipeSource::new(input, self.format, self.rate, channels))))
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Save the spectrogram of an audio file as a PNG image
+    Render(RenderArgs),
+    /// Print the average spectrum of an audio file
+    Spectrum(SpectrumArgs),
+}
+
+#[derive(Args)]
+pub struct RenderArgs {
+    /// Audio file to analyze
+    input: PathBuf,
+    /// Where to save the image
+    #[arg(short, long)]
+    output: PathBuf,
+    #[arg(long, default_value_t = 1920)]
+    width: u32,
+    #[arg(long, default_value_t = 1080)]
+    height: u32,
+    /// Name of a color scheme, e.g. "magma" or "blue-red"
+    #[arg(long, default_value = "magma")]
+    palette: String,
+    /// Length of each FFT window, in seconds
+    #[arg(long, default_value_t = DEFAULT_WINDOW_PERIOD)]
+    fft_period: Period,
+    /// Frequency range of the vertical axis, in Hz
+    #[arg(long, alias = "log-freq", value_parser = parse_range, default_value = "32..22030")]
+    freq: Range<f32>,
+    /// How frequencies are spread up the image: linear, log, octave, mel, bark or erb
+    #[arg(long, value_parser = parse_frequency_scale, default_value = "log")]
+    frequency_scale: FrequencyScale,
+    /// Range of decibels spanned by the palette
+    #[arg(long, value_parser = parse_range, allow_hyphen_values = true, default_value = "-70..-10")]
+    db_range: Range<f32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SpectrumFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Args)]
+pub struct SpectrumArgs {
+    /// Audio file to analyze
+    input: PathBuf,
+    /// Where to save the spectrum; printed to stdout if omitted
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = SpectrumFormat::Csv)]
+    format: SpectrumFormat,
+    /// Length of each FFT window, in seconds
+    #[arg(long, default_value_t = DEFAULT_WINDOW_PERIOD)]
+    fft_period: Period,
+    /// Only include frequencies in this range, in Hz
+    #[arg(long, value_parser = parse_range)]
+    freq: Option<Range<f32>>,
+    /// Combine the bins into this many bands, evenly spaced on the frequency scale
+    #[arg(long)]
+    bands: Option<usize>,
+    /// How the bands are spread: linear, log, octave, mel, bark or erb
+    #[arg(long, value_parser = parse_frequency_scale, default_value = "log", requires = "bands")]
+    frequency_scale: FrequencyScale,
+}
+
+/// Runs a headless subcommand, reporting any failure on stderr.
+pub fn run(command: Command) -> ExitCode {
+    let result = match command {
+        Command::Render(args) => render(args),
+        Command::Spectrum(args) => spectrum(args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn render(args: RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let palette = find_color_scheme(&args.palette)
+        .ok_or_else(|| format!("Unknown palette: {}", args.palette))?;
+    let options = RenderOptions {
+        width: args.width,
+        height: args.height,
+        frequency_range: args.freq,
+        frequency_scale: args.frequency_scale,
+        db_range: args.db_range,
+        palette,
+        fft_period: args.fft_period,
+    };
+    render_file(&args.input, &args.output, &options)?;
+    Ok(())
+}
+
+fn spectrum(args: SpectrumArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let clip = AudioClip::load(&args.input)?;
+    let mut spectrum = average_spectrum(&clip, args.fft_period)?;
+    if let Some(bands) = args.bands {
+        let nyquist = clip.sample_rate as f32 / 2.0;
+        let range = args.freq.as_ref()
+            .map_or(frequency_scale::default_range(nyquist), |range| (range.start.max(1.0), range.end.min(nyquist)));
+        spectrum = scaled_spectrum(&spectrum, args.frequency_scale, range, bands);
+    }
+    let spectrum = spectrum.iter()
+        .filter(|(f, _)| args.freq.as_ref().map_or(true, |range| range.contains(f)));
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let to_db = |magnitude: f32| 20.0 * (magnitude + 1e-7).log10();
+    match args.format {
+        SpectrumFormat::Csv => {
+            writeln!(out, "frequency,left_db,right_db")?;
+            for (frequency, (l, r)) in spectrum {
+                writeln!(out, "{},{},{}", frequency, to_db(*l), to_db(*r))?;
+            }
+        }
+        SpectrumFormat::Json => {
+            // The output is simple enough that it doesn't warrant a serialization library
+            writeln!(out, "[")?;
+            for (i, (frequency, (l, r))) in spectrum.enumerate() {
+                if i > 0 { writeln!(out, ",")?; }
+                write!(
+                    out,
+                    "  {{\"frequency\": {}, \"left_db\": {}, \"right_db\": {}}}",
+                    frequency, to_db(*l), to_db(*r)
+                )?;
+            }
+            writeln!(out, "\n]")?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn parse_range(value: &str) -> Result<Range<f32>, String> {
+    let (start, end) = value.split_once("..")
+        .ok_or_else(|| format!("Expected a range like 32..22030, got {}", value))?;
+    let start: f32 = start.trim().parse().map_err(|e| format!("Invalid start of range: {}", e))?;
+    let end: f32 = end.trim().parse().map_err(|e| format!("Invalid end of range: {}", e))?;
+    if start >= end {
+        return Err(format!("The start of the range must be below its end, got {}", value));
+    }
+    Ok(start..end)
+}
+
+fn parse_frequency_scale(value: &str) -> Result<FrequencyScale, String> {
+    match value.to_lowercase().as_str() {
+        "linear" => Ok(FrequencyScale::Linear),
+        "log" => Ok(FrequencyScale::Log),
+        "octave" => Ok(FrequencyScale::Octave),
+        "mel" => Ok(FrequencyScale::Mel),
+        "bark" => Ok(FrequencyScale::Bark),
+        "erb" => Ok(FrequencyScale::Erb),
+        _ => Err(format!("Expected linear, log, octave, mel, bark or erb, got {}", value)),
+    }
+}
+
+fn parse_listen_address(value: &str) -> Result<(NetworkProtocol, SocketAddr), String> {
+    let (protocol, address) = match value.split_once("://") {
+        Some(("udp", address)) => (NetworkProtocol::Rtp, address),
+        Some(("tcp", address)) => (NetworkProtocol::Tcp, address),
+        _ => return Err(format!("Expected an address like udp://0.0.0.0:5004, got {}", value)),
+    };
+    let address = address.to_socket_addrs()
+        .map_err(|e| format!("Invalid address: {}", e))?
+        .next()
+        .ok_or_else(|| format!("No addresses found for {}", address))?;
+    Ok((protocol, address))
+}
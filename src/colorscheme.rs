@@ -1,4 +1,5 @@
 use std::cell::{Cell, RefCell};
+use std::ops::Range;
 use gtk::{
     glib,
     glib::{
@@ -13,8 +14,11 @@ use colorous::*;
 use fftw::types::c32;
 use crate::fourier::StereoMagnitude;
 
-const MIN_DB: f32 = -70.0;
-const MAX_DB: f32 = -10.0;
+/// The range of levels spanned by a gradient, unless a scheme or visualizer is given another one.
+pub const DEFAULT_MIN_DB: f32 = -70.0;
+pub const DEFAULT_MAX_DB: f32 = -10.0;
+/// The narrowest range the floor and ceiling can be set to, which keeps the floor below the ceiling.
+pub const MIN_DB_SPAN: f32 = 5.0;
 
 glib::wrapper! {
     pub struct ColorScheme(ObjectSubclass<imp::ColorScheme>);
@@ -52,13 +56,18 @@ impl ColorScheme {
         }
     }
 
-    pub fn color_for(&self, (l, r): StereoMagnitude) -> (Color, f32) {
+    pub fn color_for(&self, magnitude: StereoMagnitude) -> (Color, f32) {
+        self.color_in_range(magnitude, self.min_db()..self.max_db())
+    }
+
+    /// Like `color_for`, but with the range of decibels spanned by the gradient chosen by the caller.
+    pub fn color_in_range(&self, (l, r): StereoMagnitude, db_range: Range<f32>) -> (Color, f32) {
         let imp = imp::ColorScheme::from_obj(self);
         let background = imp.background.get();
 
         let magnitude_power = c32::new(l, r).norm_sqr();
         let magnitude_db = 10.0 * (magnitude_power + 1e-7).log10();
-        let magnitude_bounded = (magnitude_db - MIN_DB) / (MAX_DB - MIN_DB);
+        let magnitude_bounded = (magnitude_db - db_range.start) / (db_range.end - db_range.start);
 
         if background.is_some() {
             // If a background is provided, the foreground is based on a diverging gradient
@@ -102,6 +111,11 @@ mod imp {
         pub name: RefCell<String>,
         pub gradient: Cell<Gradient>,
         pub background: Cell<Option<Color>>,
+        /// The levels at either end of the gradient, used by `color_for`
+        #[property(get, set)]
+        pub min_db: Cell<f32>,
+        #[property(get, set)]
+        pub max_db: Cell<f32>,
     }
 
     #[glib::object_subclass]
@@ -114,6 +128,8 @@ mod imp {
                 name: String::new().into(),
                 gradient: colorous::GREYS.into(),
                 background: None.into(),
+                min_db: DEFAULT_MIN_DB.into(),
+                max_db: DEFAULT_MAX_DB.into(),
             }
         }
     }
@@ -148,4 +164,15 @@ pub fn default_color_schemes() -> ListStore {
         ColorScheme::new_mono(ORANGES, "Oranges"),
     ]);
     list
-}
\ No newline at end of file
+}
+
+/// Finds one of the default color schemes by name, ignoring case and the "(Stereo)" suffix.
+pub fn find_color_scheme(name: &str) -> Option<ColorScheme> {
+    let name = name.trim().to_lowercase();
+    default_color_schemes().iter::<ColorScheme>()
+        .filter_map(Result::ok)
+        .find(|scheme| {
+            let scheme_name = scheme.name().to_lowercase();
+            scheme_name == name || scheme_name.trim_end_matches(" (stereo)") == name
+        })
+}
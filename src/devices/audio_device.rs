@@ -1,10 +1,8 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use cpal::default_host;
 use cpal::Device;
 use cpal::traits::DeviceTrait;
-use cpal::traits::HostTrait;
 use gtk::{
     glib,
     glib::{
@@ -20,7 +18,7 @@ glib::wrapper! {
 }
 
 impl AudioDevice {
-    pub fn get_device(&self) -> Rc<Device> {
+    pub fn get_device(&self) -> Option<Rc<Device>> {
         let imp = imp::AudioDevice::from_obj(self);
         imp.device.borrow().clone()
     }
@@ -30,7 +28,7 @@ impl From<Rc<Device>> for AudioDevice {
     fn from(device: Rc<Device>) -> AudioDevice {
         let object = Object::builder().build();
         let imp = imp::AudioDevice::from_obj(&object);
-        imp.device.replace(device);
+        imp.device.replace(Some(device));
         object
     }
 }
@@ -42,12 +40,15 @@ mod imp {
     #[properties(wrapper_type = super::AudioDevice)]
     pub struct AudioDevice {
         #[property(name = "name", get = Self::get_name, type = String)]
-        pub device: RefCell<Rc<Device>>,
+        pub device: RefCell<Option<Rc<Device>>>,
     }
 
     impl AudioDevice {
         fn get_name(&self) -> String {
-            self.device.borrow().name().unwrap()
+            // Some backends can fail to report a name for a device which is still usable
+            self.device.borrow().as_ref()
+                .and_then(|device| device.name().ok())
+                .unwrap_or_else(|| "Unknown device".into())
         }
     }
 
@@ -58,7 +59,7 @@ mod imp {
 
         fn new() -> Self {
             Self {
-                device: Rc::new(default_host().default_input_device().unwrap()).into()
+                device: None.into()
             }
         }
     }
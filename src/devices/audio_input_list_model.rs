@@ -1,21 +1,32 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::sync::OnceLock;
+use std::sync::atomic::Ordering;
 use itertools::Itertools;
-use cpal::{ChannelCount, InputCallbackInfo, SampleRate, SizedSample, Stream, StreamConfig};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::traits::{DeviceTrait, HostTrait};
 use gtk::{
     glib,
     glib::*,
-    glib::property::*,
+    glib::subclass::Signal,
     subclass::prelude::*,
     gio::ListModel,
     prelude::*,
 };
-use ringbuf::{HeapRb, HeapProd, HeapCons, traits::{Producer, Split}};
+use ringbuf::{HeapRb, HeapCons, traits::Split};
 use crate::fourier::StereoMagnitude;
 use crate::devices::audio_device::AudioDevice;
-use std::sync::{Arc, Mutex};
+use crate::sources::{
+    AudioInputError, AudioSource, SampleProducer, SampleTaps, Subscribers, StreamEvent, StreamStatistics,
+    cpal_source::CpalSource,
+    history::{HistoryTap, SampleHistory},
+    recorder::{Recorder, RecordingFormat, default_recording_path},
+};
+use std::path::PathBuf;
+use std::sync::Arc;
 
+const DEFAULT_HISTORY_SECONDS: f64 = 60.0;
+const SUBSCRIBER_BUFFER_SIZE: usize = 4096;
+const STATISTICS_SYNC_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
 glib::wrapper! {
     pub struct AudioInputListModel(ObjectSubclass<imp::AudioInputListModel>)
@@ -23,73 +34,152 @@ glib::wrapper! {
 }
 
 impl AudioInputListModel {
-    pub fn new() -> (AudioInputListModel, HeapCons<StereoMagnitude>) {
-        let object = Object::builder().build();
-        let imp = imp::AudioInputListModel::from_obj(&object);
+    pub fn new() -> AudioInputListModel {
+        Object::builder().build()
+    }
+
+    /// Creates a new stream of the input's samples, which is fed alongside any others.
+    ///
+    /// Dropping the returned consumer unsubscribes it.
+    pub fn subscribe(&self) -> HeapCons<StereoMagnitude> {
+        let imp = imp::AudioInputListModel::from_obj(self);
+        let (producer, consumer) = HeapRb::new(SUBSCRIBER_BUFFER_SIZE).split();
+        imp.subscribers.lock().unwrap().push(producer.into());
+        consumer
+    }
+
+    /// Re-enumerates the input devices of the host, replacing the current list.
+    pub fn refresh(&self) -> Result<(), AudioInputError> {
+        let imp = imp::AudioInputListModel::from_obj(self);
+        let devices = imp::enumerate_devices(&imp.host)?;
+        let removed = imp.devices.borrow().len() as u32;
+        let added = devices.len() as u32;
+        imp.devices.replace(devices);
+        self.items_changed(0, removed, added);
+        Ok(())
+    }
+
+    pub fn select(&self, device_index: u32) -> Result<(), AudioInputError> {
+        let imp = imp::AudioInputListModel::from_obj(self);
 
-        let (sender, receiver) = HeapRb::new(4096).split();
-        imp.sender.set(sender);
-        (object, receiver)
+        // Release the current device before opening another
+        self.stop();
+        let device = imp.item(device_index)
+            .and_then(|item| item.downcast::<AudioDevice>().ok())
+            .and_then(|device| device.get_device())
+            .ok_or(AudioInputError::NoDevice)?;
+        self.set_source(Box::new(CpalSource::new(device)?))
     }
 
-    pub fn select(&self, device_index: u32) {
+    /// Replaces the current source, and starts feeding its samples to the visualizers.
+    pub fn set_source(&self, mut source: Box<dyn AudioSource>) -> Result<(), AudioInputError> {
         let imp = imp::AudioInputListModel::from_obj(self);
-        let mut stream = imp.stream.lock().unwrap();
-        let mut config = imp.config.lock().unwrap();
-
-        // If there's an existing stream, close it
-        stream.take().map(|s: Stream| {
-            s.pause().expect("Failed to stop a running stream")
-        });
-
-        // Set up a stream config and report which device was selected
-        let device = imp.item(device_index).unwrap()
-            .dynamic_cast_ref::<AudioDevice>().unwrap()
-            .get_device();
-        let c = device.as_ref().default_input_config().unwrap().config();
-        *config = device.as_ref().default_input_config().unwrap().config().into();
-        let channels = config.as_ref().unwrap().channels;
-        let sample_rate = config.as_ref().unwrap().sample_rate;
-        imp.sample_rate.replace(sample_rate.0);
+        self.stop();
+
+        imp.sample_rate.replace(source.sample_rate());
         self.notify_sample_rate();
+        imp.statistics.reset();
+        imp.sync_statistics();
+        imp.history.reset(source.sample_rate(), source.channels());
         println!(
-            "Listening to device: {} ({}Hz, {}ch)",
-            device.name().unwrap(),
-            sample_rate.0,
-            channels
+            "Listening to: {} ({}Hz, {}ch)",
+            source.name(),
+            source.sample_rate(),
+            source.channels().count()
         );
 
-        // Create an input stream with the selected device
-        let sender = Arc::clone(&imp.sender);
-        *stream = device.build_input_stream(
-            config.as_ref().unwrap(),
-            move |data: &[f32], _| {
-                if channels == 1 {
-                    let mut mono_expanded = data.iter().map(|s| (*s, *s));
-                    sender.lock().unwrap().push_iter(&mut mono_expanded);
-                } else if channels == 2 {
-                    let mut stereo_expanded = data.iter().tuples().map(|(l, r)| (*l, *r));
-                    sender.lock().unwrap().push_iter(&mut stereo_expanded);
-                } else {
-                    eprintln!("{}-channel input not supported!", channels);
-                }
-            },
-            |err| eprintln!("An error occurred on the input audio stream: {}", err),
-            None,
-        ).ok();
+        let producer = SampleProducer::new(
+            Arc::clone(&imp.subscribers),
+            Arc::clone(&imp.statistics),
+            Arc::clone(&imp.taps),
+            imp.event_sender.clone(),
+        );
+        imp.producer.replace(Some(producer.clone()));
+        source.start(producer)?;
+        imp.source_name.replace(source.name());
+        self.notify_source_name();
+        imp.source.replace(Some(source));
+        Ok(())
+    }
 
-        // Start the newly created stream (usually not necessary)
-        stream.as_ref().map(|s| s.play().expect("Failed to start input stream"));
+    /// Stops the current source, if there is one.
+    ///
+    /// Any recording is finished too, since the next source may have a different format.
+    pub fn stop(&self) {
+        let imp = imp::AudioInputListModel::from_obj(self);
+        self.stop_recording();
+        // Anything the old source pushes from here on is dropped, even if its thread outlives it
+        if let Some(producer) = imp.producer.take() {
+            producer.retire();
+        }
+        if let Some(mut source) = imp.source.take() {
+            source.stop();
+        }
+        imp.source_name.replace(String::new());
+        self.notify_source_name();
     }
 
-    pub fn current_stream(&self) -> Arc<Mutex<Option<Stream>>> {
+    /// Starts recording the current source to a new file, returning its path.
+    pub fn start_recording(&self) -> Result<PathBuf, AudioInputError> {
         let imp = imp::AudioInputListModel::from_obj(self);
-        Arc::clone(&imp.stream)
+        self.stop_recording();
+        let (sample_rate, channels) = imp.source.borrow().as_ref()
+            .map(|source| (source.sample_rate(), source.channels()))
+            .ok_or(AudioInputError::NoDevice)?;
+        let format = imp.recording_format.get();
+        let path = default_recording_path(format);
+        let recorder = Recorder::start(
+            path.clone(),
+            format,
+            sample_rate,
+            channels,
+            &imp.taps,
+            imp.event_sender.clone(),
+        )?;
+        imp.recorder.replace(Some(recorder));
+        imp.recording.set(true);
+        self.notify_recording();
+        Ok(path)
     }
 
-    pub fn current_config(&self) -> Arc<Mutex<Option<StreamConfig>>> {
+    /// Finishes the current recording, if there is one.
+    ///
+    /// `recording-finished` is emitted once the file has been written, or `stream-error` if that fails.
+    pub fn stop_recording(&self) {
         let imp = imp::AudioInputListModel::from_obj(self);
-        Arc::clone(&imp.config)
+        let Some(recorder) = imp.recorder.take() else { return };
+        recorder.stop(&imp.taps);
+        imp.recording.set(false);
+        self.notify_recording();
+    }
+
+    /// Starts saving the last `history-seconds` of input to a new file, returning its path.
+    ///
+    /// `recording-finished` is emitted once the file has been written, or `stream-error` if that fails.
+    pub fn save_history(&self) -> Result<PathBuf, AudioInputError> {
+        let imp = imp::AudioInputListModel::from_obj(self);
+        let format = imp.recording_format.get();
+        let path = default_recording_path(format);
+        imp.history.save(path.clone(), format, imp.event_sender.clone())?;
+        Ok(path)
+    }
+
+    pub fn connect_recording_finished<F: Fn(&Self, &str) + 'static>(&self, f: F) -> SignalHandlerId {
+        self.connect_local("recording-finished", false, move |values| {
+            let model = values[0].get::<Self>().unwrap();
+            let path = values[1].get::<String>().unwrap();
+            f(&model, &path);
+            None
+        })
+    }
+
+    pub fn connect_stream_error<F: Fn(&Self, &str) + 'static>(&self, f: F) -> SignalHandlerId {
+        self.connect_local("stream-error", false, move |values| {
+            let model = values[0].get::<Self>().unwrap();
+            let message = values[1].get::<String>().unwrap();
+            f(&model, &message);
+            None
+        })
     }
 }
 
@@ -99,14 +189,96 @@ mod imp {
     #[derive(Properties)]
     #[properties(wrapper_type = super::AudioInputListModel)]
     pub struct AudioInputListModel {
-        devices: Vec<Rc<cpal::Device>>,
-        pub _host: cpal::Host,
-        pub stream: Arc<Mutex<Option<Stream>>>,
-        pub config: Arc<Mutex<Option<StreamConfig>>>,
-        pub sender: Arc<Mutex<HeapProd<StereoMagnitude>>>,
+        pub devices: RefCell<Vec<Rc<cpal::Device>>>,
+        pub host: cpal::Host,
+        pub source: RefCell<Option<Box<dyn AudioSource>>>,
+        /// The current source's producer, kept so that it can be retired when the source stops
+        pub producer: RefCell<Option<SampleProducer>>,
+        pub subscribers: Subscribers,
+        pub statistics: Arc<StreamStatistics>,
+        pub taps: Arc<SampleTaps>,
+        pub recorder: RefCell<Option<Recorder>>,
+        pub history: Arc<SampleHistory>,
+        pub event_sender: async_channel::Sender<StreamEvent>,
+        pub event_receiver: async_channel::Receiver<StreamEvent>,
 
         #[property(get)]
         pub sample_rate: RefCell<u32>,
+        #[property(get)]
+        pub source_name: RefCell<String>,
+
+        #[property(get)]
+        pub overruns: Cell<u64>,
+        #[property(get)]
+        pub underruns: Cell<u64>,
+        #[property(get)]
+        pub dropped_samples: Cell<u64>,
+        /// Total number of overruns and underruns, useful for marking gaps in the visualizers
+        #[property(get)]
+        pub discontinuities: Cell<u64>,
+
+        /// Whether the raw input is being written to a file
+        #[property(get, set = Self::set_recording)]
+        pub recording: Cell<bool>,
+        /// How much of the input is kept in memory, so that it can be saved after the fact
+        #[property(get, set = Self::set_history_seconds, minimum = 0.0)]
+        pub history_seconds: Cell<f64>,
+        /// What kind of file recordings and saved history are written as
+        #[property(get, set, builder(RecordingFormat::default()))]
+        pub recording_format: Cell<RecordingFormat>,
+    }
+
+    impl AudioInputListModel {
+        fn set_recording(&self, recording: bool) {
+            let obj = self.obj();
+            if !recording {
+                obj.stop_recording();
+            } else if !self.recording.get() {
+                // Property setters can't fail, so problems are reported like any other stream error
+                if let Err(err) = obj.start_recording() {
+                    obj.emit_by_name::<()>("stream-error", &[&err.to_string()]);
+                    // Bindings ignore notifications while they're setting a value, so resync them afterwards
+                    glib::idle_add_local_once(clone!(
+                        #[weak] obj,
+                        move || obj.notify_recording()
+                    ));
+                }
+            }
+        }
+
+        fn set_history_seconds(&self, seconds: f64) {
+            self.history_seconds.set(seconds);
+            self.history.set_seconds(seconds);
+        }
+
+        pub fn sync_statistics(&self) {
+            let overruns = self.statistics.overruns.load(Ordering::Relaxed);
+            let underruns = self.statistics.underruns.load(Ordering::Relaxed);
+            let dropped_samples = self.statistics.dropped_samples.load(Ordering::Relaxed);
+            if self.overruns.replace(overruns) != overruns {
+                self.obj().notify_overruns();
+            }
+            if self.underruns.replace(underruns) != underruns {
+                self.obj().notify_underruns();
+            }
+            if self.dropped_samples.replace(dropped_samples) != dropped_samples {
+                self.obj().notify_dropped_samples();
+            }
+            if self.discontinuities.replace(overruns + underruns) != overruns + underruns {
+                self.obj().notify_discontinuities();
+            }
+        }
+    }
+
+    pub fn enumerate_devices(host: &cpal::Host) -> Result<Vec<Rc<cpal::Device>>, AudioInputError> {
+        let default_device_name = host.default_input_device()
+            .and_then(|d| d.name().ok());
+        Ok(
+            host.input_devices()?
+                .sorted_by_cached_key(|d| d.name().ok() != default_device_name)
+                .map(Rc::from)
+                .collect()
+        )
     }
 
     #[glib::object_subclass]
@@ -116,26 +288,86 @@ mod imp {
         type Interfaces = (ListModel, );
 
         fn new() -> Self {
-            let _host = cpal::default_host();
-            let default_device_name = _host.default_input_device().unwrap().name().unwrap();
-            let devices = _host.input_devices().unwrap()
-                .sorted_by_cached_key(|d| { return d.name().unwrap() != default_device_name; })
-                .map(Rc::from)
-                .collect();
-            let (dummy_sender, _) = HeapRb::new(1).split();
+            let host = cpal::default_host();
+            let devices = enumerate_devices(&host).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                vec![]
+            });
+            let (event_sender, event_receiver) = async_channel::bounded(64);
             Self {
-                _host,
-                stream: Arc::new(None.into()),
-                config: Arc::new(None.into()),
-                sender: Arc::new(dummy_sender.into()),
-                devices,
+                host,
+                source: None.into(),
+                producer: None.into(),
+                subscribers: Default::default(),
+                statistics: Default::default(),
+                taps: Default::default(),
+                recorder: None.into(),
+                history: Arc::new(SampleHistory::new(DEFAULT_HISTORY_SECONDS)),
+                event_sender,
+                event_receiver,
+                devices: devices.into(),
                 sample_rate: 0.into(),
+                source_name: String::new().into(),
+                overruns: 0.into(),
+                underruns: 0.into(),
+                dropped_samples: 0.into(),
+                discontinuities: 0.into(),
+                recording: false.into(),
+                history_seconds: DEFAULT_HISTORY_SECONDS.into(),
+                recording_format: RecordingFormat::default().into(),
             }
         }
     }
 
     #[glib::derived_properties]
-    impl ObjectImpl for AudioInputListModel {}
+    impl ObjectImpl for AudioInputListModel {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+            SIGNALS.get_or_init(|| {
+                vec![
+                    Signal::builder("stream-error")
+                        .param_types([String::static_type()])
+                        .build(),
+                    Signal::builder("recording-finished")
+                        .param_types([String::static_type()])
+                        .build(),
+                ]
+            })
+        }
+
+        fn constructed(&self) {
+            self.parent_constructed();
+            self.taps.add(Box::new(HistoryTap(Arc::clone(&self.history))));
+
+            // Gaps are reported as events, but those are dropped when the channel is full, so the counters are
+            // also copied across on a timer
+            let model = self.obj().downgrade();
+            glib::timeout_add_local(STATISTICS_SYNC_INTERVAL, move || {
+                let Some(model) = model.upgrade() else { return ControlFlow::Break };
+                model.imp().sync_statistics();
+                ControlFlow::Continue
+            });
+
+            // Handle events reported by the audio thread on the main loop
+            let receiver = self.event_receiver.clone();
+            let model = self.obj().downgrade();
+            glib::spawn_future_local(async move {
+                while let Ok(event) = receiver.recv().await {
+                    let Some(model) = model.upgrade() else { break };
+                    match event {
+                        StreamEvent::Error(message) => {
+                            eprintln!("An error occurred on the input audio stream: {}", message);
+                            model.emit_by_name::<()>("stream-error", &[&message]);
+                        }
+                        StreamEvent::Discontinuity => model.imp().sync_statistics(),
+                        StreamEvent::Saved(path) => {
+                            model.emit_by_name::<()>("recording-finished", &[&path.to_string_lossy().into_owned()]);
+                        }
+                    }
+                }
+            });
+        }
+    }
 
     impl ListModelImpl for AudioInputListModel {
         fn item_type(&self) -> Type {
@@ -143,11 +375,11 @@ mod imp {
         }
 
         fn n_items(&self) -> u32 {
-            self.devices.len() as u32
+            self.devices.borrow().len() as u32
         }
 
         fn item(&self, position: u32) -> Option<Object> {
-            self.devices.iter()
+            self.devices.borrow().iter()
                 .nth(position as usize)
                 .map(|device| { AudioDevice::from(device.clone()).into() })
         }
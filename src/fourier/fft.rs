@@ -8,6 +8,10 @@ use num_traits::{FloatConst, Zero};
 use crate::fourier::audio_transform::AudioTransform;
 use crate::fourier::{Period, Frequency, StereoMagnitude};
 
+/// How much input each frame is transformed from, in seconds; long enough to tell apart frequencies
+/// about 20 Hz apart, while still following quick changes
+pub const DEFAULT_WINDOW_PERIOD: Period = 0.05;
+
 pub struct FastFourierTransform {
     plan: C2CPlan32,
     sample_rate: Frequency,
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use crate::fourier::{Frequency, StereoMagnitude};
+
+/// Keeps a copy of the most recent FFT frames on the CPU, so values can be read back from what's on screen.
+pub struct FrameHistory {
+    frames: VecDeque<Vec<StereoMagnitude>>,
+    capacity: usize,
+}
+
+impl FrameHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Copies a frame in, reusing the oldest frame's allocation once the history is full.
+    pub fn push(&mut self, frame: &[StereoMagnitude]) {
+        let mut recycled = if self.frames.len() >= self.capacity { self.frames.pop_front() } else { None }
+            .unwrap_or_default();
+        recycled.clear();
+        recycled.extend_from_slice(frame);
+        self.frames.push_back(recycled);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Changes how many frames are kept, dropping the oldest ones if there are too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        while self.frames.len() > capacity {
+            self.frames.pop_front();
+        }
+        self.capacity = capacity;
+    }
+
+    /// Iterates over the frames from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item=&[StereoMagnitude]> {
+        self.frames.iter().map(Vec::as_slice)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Returns a frame counting back from the newest one, which is `0`.
+    pub fn frame(&self, frames_ago: usize) -> Option<&[StereoMagnitude]> {
+        let index = self.frames.len().checked_sub(frames_ago + 1)?;
+        self.frames.get(index).map(Vec::as_slice)
+    }
+
+    /// Looks up the bin containing a frequency, in a frame which spans everything up to the nyquist frequency.
+    ///
+    /// Frequencies above the nyquist frequency weren't measured, so they have no magnitude.
+    pub fn magnitude_at(&self, frames_ago: usize, frequency: Frequency, nyquist: Frequency) -> Option<StereoMagnitude> {
+        if frequency > nyquist { return None; }
+        let frame = self.frame(frames_ago)?;
+        // This matches the way the shader samples the fft texture
+        let index = (frequency / nyquist * frame.len() as Frequency) as usize;
+        frame.get(index.min(frame.len().checked_sub(1)?)).copied()
+    }
+
+    /// Finds the magnitudes at two fractions (from 0 to 1) of the way through the sorted frames, ordered by power.
+    ///
+    /// The frames span `count` back from the one `newest` frames ago, such as whatever a frozen display shows.
+    /// Long histories are sampled evenly down to roughly `max_samples` values first, which is plenty for percentiles.
+    /// They're sorted in `samples`, which can be kept between calls to avoid allocating it each time.
+    pub fn percentiles(
+        &self,
+        (newest, count): (usize, usize),
+        low: f32,
+        high: f32,
+        max_samples: usize,
+        samples: &mut Vec<StereoMagnitude>,
+    ) -> Option<(StereoMagnitude, StereoMagnitude)> {
+        let bins = self.frames.back()?.len();
+        let end = self.frames.len().checked_sub(newest)?;
+        let count = count.min(end);
+        let stride = (count * bins / max_samples.max(1)).max(1);
+        samples.clear();
+        samples.extend(self.frames.range(end - count..end).step_by(stride).flatten().copied());
+        let last = samples.len().checked_sub(1)?;
+        let mut nth = |fraction: f32| {
+            let index = (fraction.clamp(0.0, 1.0) * last as f32) as usize;
+            *samples.select_nth_unstable_by(index, |(l0, r0), (l1, r1)| {
+                (l0 * l0 + r0 * r0).total_cmp(&(l1 * l1 + r1 * r1))
+            }).1
+        };
+        Some((nth(low), nth(high)))
+    }
+}
@@ -89,7 +89,7 @@ fn cosine_interpolate(data: &[c32], index: f32) -> StereoMagnitude {
 fn cubic_interpolate(data: &[c32], index: f32) -> StereoMagnitude {
     // Adapted from: https://paulbourke.net/miscellaneous/interpolation/
     let mu = index - index.floor();
-    let x0 = (index.floor() as usize - 1).max(0);
+    let x0 = (index.floor() as usize).saturating_sub(1);
     let x1 = index.floor() as usize;
     let x2 = (x1 + 1).min(data.len() - 1);
     let x3 = (x1 + 2).min(data.len() - 1);
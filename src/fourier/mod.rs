@@ -3,6 +3,7 @@ use std::ops::Range;
 pub mod interpolated_frequency_sample;
 pub mod fft;
 pub mod audio_transform;
+pub mod frame_history;
 
 const FFT_WINDOW_SIZE: usize = 2048;
 const PADDED_FFT_WINDOW_SIZE: usize = FFT_WINDOW_SIZE * 2;
@@ -0,0 +1,113 @@
+use gtk::glib;
+
+use crate::fourier::Frequency;
+
+/// The bottom of the frequency axis, unless it's zoomed; lower frequencies take longer windows than are used
+pub const MIN_FREQUENCY: Frequency = 32.0;
+/// The top of the frequency axis, for inputs which reach beyond it (and until an input's sample rate is known)
+pub const MAX_FREQUENCY: Frequency = 22030.0;
+
+/// The frequencies shown for an input, which stop at its nyquist frequency when that's lower than usual.
+pub fn default_range(nyquist_frequency: Frequency) -> (Frequency, Frequency) {
+    (MIN_FREQUENCY, MAX_FREQUENCY.min(nyquist_frequency))
+}
+
+/// How frequencies are spread along the frequency axis of a spectrogram.
+///
+/// The perceptual scales (mel, bark and ERB) give more room to the frequencies the ear resolves best,
+/// while the octave scale is logarithmic with musical steps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "SpectrogramFrequencyScale")]
+pub enum FrequencyScale {
+    Linear = 0,
+    #[default]
+    Log = 1,
+    Mel = 2,
+    Bark = 3,
+    Erb = 4,
+    Octave = 5,
+}
+
+impl FrequencyScale {
+    pub const ALL: [FrequencyScale; 6] = [Self::Linear, Self::Log, Self::Octave, Self::Mel, Self::Bark, Self::Erb];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Linear => "Linear",
+            Self::Log => "Logarithmic",
+            Self::Octave => "Octaves",
+            Self::Mel => "Mel",
+            Self::Bark => "Bark",
+            Self::Erb => "ERB",
+        }
+    }
+
+    pub fn position(&self) -> u32 {
+        Self::ALL.iter().position(|scale| scale == self).unwrap() as u32
+    }
+
+    /// Converts a frequency to this scale's units, in which equal steps are equally far apart on screen.
+    pub fn warp(&self, frequency: Frequency) -> f32 {
+        match self {
+            Self::Linear => frequency,
+            Self::Log => frequency.ln(),
+            Self::Octave => frequency.log2(),
+            Self::Mel => 2595.0 * (1.0 + frequency / 700.0).log10(),
+            // Traunmüller's approximation, since it can be inverted exactly
+            Self::Bark => 26.81 * frequency / (1960.0 + frequency) - 0.53,
+            Self::Erb => 21.4 * (1.0 + 0.00437 * frequency).log10(),
+        }
+    }
+
+    pub fn unwarp(&self, value: f32) -> Frequency {
+        match self {
+            Self::Linear => value,
+            Self::Log => value.exp(),
+            Self::Octave => value.exp2(),
+            Self::Mel => 700.0 * (10f32.powf(value / 2595.0) - 1.0),
+            Self::Bark => 1960.0 * (value + 0.53) / (26.28 - value),
+            Self::Erb => (10f32.powf(value / 21.4) - 1.0) / 0.00437,
+        }
+    }
+
+    /// Places a frequency along an axis spanning `(min, max)`, from 0 at the start to 1 at the end.
+    pub fn map(&self, frequency: Frequency, (min, max): (Frequency, Frequency)) -> f32 {
+        let (min, max) = (self.warp(min), self.warp(max));
+        (self.warp(frequency) - min) / (max - min)
+    }
+
+    /// The frequency found a fraction of the way along an axis spanning `(min, max)`.
+    pub fn unmap(&self, fraction: f32, (min, max): (Frequency, Frequency)) -> Frequency {
+        let (min, max) = (self.warp(min), self.warp(max));
+        self.unwarp(min + (max - min) * fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwarp_inverts_warp() {
+        for scale in FrequencyScale::ALL {
+            for frequency in [1.0, 20.0, 440.0, 1000.0, 8000.0, 22050.0, 96000.0] {
+                let round_trip = scale.unwarp(scale.warp(frequency));
+                assert!(
+                    (round_trip - frequency).abs() <= 1e-4 * frequency,
+                    "{:?} turned {} Hz into {} Hz", scale, frequency, round_trip,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unmap_inverts_map() {
+        let range = (32.0, 22050.0);
+        for scale in FrequencyScale::ALL {
+            for fraction in [0.0, 0.25, 0.5, 0.75, 1.0] {
+                let round_trip = scale.map(scale.unmap(fraction, range), range);
+                assert!((round_trip - fraction).abs() <= 1e-4, "{:?} turned {} into {}", scale, fraction, round_trip);
+            }
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use std::cell::RefCell;
 use std::ptr;
 
 use adw::ColorScheme;
 use adw::glib::clone;
 use adw::prelude::AdwApplicationExt;
-use gtk::{DropDown, glib, Align, RevealerTransitionType, Overlay, GraphicsOffload};
+use gtk::{DropDown, glib, Align, RevealerTransitionType, Overlay};
+use clap::Parser;
 use gtk::prelude::*;
 use itertools::Itertools;
 
@@ -11,21 +13,47 @@ use devices::audio_device::AudioDevice;
 use devices::audio_input_list_model::AudioInputListModel;
 
 use crate::colorscheme::*;
-use crate::widgets::gpu_spectrogram::GPUSpectrogram;
-use crate::widgets::oscilloscope::Oscilloscope;
-use crate::widgets::simple_spectrogram::SimpleSpectrogram;
+use crate::frequency_scale::FrequencyScale;
+use crate::sources::AudioSource;
+use crate::sources::file_source::FileSource;
+use crate::sources::generator_source::GeneratorSource;
+use crate::sources::recorder::RecordingFormat;
+use crate::widgets::pane_layout::PaneLayout;
+use crate::widgets::playback_controls::PlaybackControls;
+use crate::widgets::spectrogram_orientation::SpectrogramOrientation;
+use crate::widgets::spectrum_analyzer::{SpectrumAveraging, SpectrumStyle};
+use crate::widgets::visualizer::{default_snapshot_path, VisualizerExt};
+use crate::widgets::visualizer_kind::VisualizerKind;
 
 mod fourier;
 mod widgets;
 mod devices;
+mod sources;
 
 mod log_scaling;
+mod frequency_scale;
+mod output_paths;
 mod colorscheme;
+mod offline_renderer;
+mod cli;
 
 const APP_ID: &str = "nl.campolattaro.jackson.spectrogram";
 
 fn main() -> glib::ExitCode {
 
+    // Subcommands run headless, so they're handled before anything touches the display
+    let cli = cli::Cli::parse();
+    if let Some(command) = cli.command {
+        return cli::run(command);
+    }
+    let initial_source = match cli.input.source() {
+        Ok(source) => RefCell::new(source),
+        Err(err) => {
+            eprintln!("{}", err);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
     // Load GL pointers from epoxy (GL context management library used by GTK).
     {
         #[cfg(target_os = "macos")]
@@ -53,26 +81,30 @@ fn main() -> glib::ExitCode {
     app.style_manager().set_color_scheme(ColorScheme::PreferDark);
 
     // Setup fft & UI on startup
-    app.connect_activate(build_ui);
+    app.connect_activate(move |app| build_ui(app, initial_source.take()));
 
-    // Run the application
-    app.run()
+    // Run the application; the arguments were already handled above, so GTK doesn't need to see them
+    app.run_with_args(&std::env::args().take(1).collect_vec())
 }
 
-fn build_ui(app: &adw::Application) {
+fn build_ui(app: &adw::Application, initial_source: Option<Box<dyn AudioSource>>) {
 
 
     // Set up an input list with its associated stream
-    let (input_list, sample_receiver) = AudioInputListModel::new();
+    let input_list = AudioInputListModel::new();
+
+    // Create visualizers for the data coming from input list, arranged the same way as last time
+    let layout = PaneLayout::new(&input_list);
+    layout.load();
 
-    // Create a visualizer for the data coming from input list
-    let visualizer = GPUSpectrogram::new(sample_receiver);
-    // let visualizer = SimpleSpectrogram::new(sample_receiver);
-    // let visualizer = PlaceholderVisualizer::new(sample_receiver);
-    // let visualizer = Oscilloscope::new(sample_receiver);
-    input_list.bind_property("sample-rate", &visualizer, "sample-rate").build();
-    let offloaded_visualizer = GraphicsOffload::new((&visualizer).into());
-    offloaded_visualizer.set_black_background(true);
+    // Errors are reported as toasts over the content
+    let toast_overlay = adw::ToastOverlay::new();
+    input_list.connect_stream_error(clone!(
+        #[weak] toast_overlay,
+        move |_, message| {
+            show_toast(&toast_overlay, message);
+        }
+    ));
 
     // Use a dropdown to select inputs
     let input_dropdown = DropDown::builder()
@@ -83,10 +115,167 @@ fn build_ui(app: &adw::Application) {
             "name",
         ))
         .build();
-    input_dropdown.connect_selected_item_notify(move |dropdown: &DropDown| {
-        input_list.select(dropdown.selected());
-    });
-    input_dropdown.notify("selected-item");
+    // Files are played with transport controls, which are hidden for live inputs
+    let playback_controls = PlaybackControls::new();
+    input_dropdown.connect_selected_item_notify(clone!(
+        #[weak] input_list,
+        #[weak] toast_overlay,
+        #[weak] playback_controls,
+        move |dropdown: &DropDown| {
+            // The selection is invalid while the list is empty, or while a file is playing
+            if dropdown.selected() == gtk::INVALID_LIST_POSITION { return; }
+            playback_controls.set_playback(None, None);
+            if let Err(err) = input_list.select(dropdown.selected()) {
+                show_toast(&toast_overlay, &err.to_string());
+            }
+        }
+    ));
+
+    // Use a file dialog to play recordings through the visualizer
+    let open_button = gtk::Button::builder()
+        .icon_name("document-open-symbolic")
+        .tooltip_text("Open Audio File")
+        .build();
+    open_button.connect_clicked(clone!(
+        #[weak] input_list,
+        #[weak] input_dropdown,
+        #[weak] toast_overlay,
+        #[weak] playback_controls,
+        move |button| {
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some("Audio Files"));
+            filter.add_mime_type("audio/*");
+            for suffix in ["wav", "flac", "mp3", "ogg"] {
+                filter.add_suffix(suffix);
+            }
+            let filters = gtk::gio::ListStore::new::<gtk::FileFilter>();
+            filters.append(&filter);
+            let dialog = gtk::FileDialog::builder()
+                .title("Open Audio File")
+                .filters(&filters)
+                .build();
+            let window = button.root().and_downcast::<gtk::Window>();
+            dialog.open(window.as_ref(), gtk::gio::Cancellable::NONE, clone!(
+                #[weak] input_list,
+                #[weak] input_dropdown,
+                #[weak] toast_overlay,
+                #[weak] playback_controls,
+                move |result| {
+                    // Errors here usually mean the dialog was dismissed
+                    let Some(path) = result.ok().and_then(|file| file.path()) else { return };
+                    let source = match FileSource::open(&path) {
+                        Ok(source) => source,
+                        Err(err) => return show_toast(&toast_overlay, &err.to_string()),
+                    };
+                    let (playback, duration) = (source.playback(), source.duration());
+                    // Deselect the device, so it can be picked again afterwards
+                    input_dropdown.set_selected(gtk::INVALID_LIST_POSITION);
+                    match input_list.set_source(Box::new(source)) {
+                        Ok(()) => playback_controls.set_playback(Some(playback), duration),
+                        Err(err) => show_toast(&toast_overlay, &err.to_string()),
+                    }
+                }
+            ));
+        }
+    ));
+
+    // Test signals can be played from a menu, for demos and for machines without an input
+    let generator_menu = gtk::gio::Menu::new();
+    for (index, preset) in GeneratorSource::presets().iter().enumerate() {
+        let item = gtk::gio::MenuItem::new(Some(&preset.name()), None);
+        item.set_action_and_target_value(Some("generator.play"), Some(&(index as u32).to_variant()));
+        generator_menu.append_item(&item);
+    }
+    let generator_button = gtk::MenuButton::builder()
+        .icon_name("audio-x-generic-symbolic")
+        .tooltip_text("Play Test Signal")
+        .menu_model(&generator_menu)
+        .build();
+    let play_generator = gtk::gio::SimpleAction::new("play", Some(&u32::static_variant_type()));
+    play_generator.connect_activate(clone!(
+        #[weak] input_list,
+        #[weak] input_dropdown,
+        #[weak] toast_overlay,
+        #[weak] playback_controls,
+        move |_, parameter| {
+            let Some(index) = parameter.and_then(|p| p.get::<u32>()) else { return };
+            let Some(source) = GeneratorSource::presets().into_iter().nth(index as usize) else { return };
+            // Deselect the device, so it can be picked again afterwards
+            input_dropdown.set_selected(gtk::INVALID_LIST_POSITION);
+            playback_controls.set_playback(None, None);
+            if let Err(err) = input_list.set_source(Box::new(source)) {
+                show_toast(&toast_overlay, &err.to_string());
+            }
+        }
+    ));
+    let generator_actions = gtk::gio::SimpleActionGroup::new();
+    generator_actions.add_action(&play_generator);
+    generator_button.insert_action_group("generator", Some(&generator_actions));
+
+    // Record the raw input, so that anything interesting can be revisited later
+    let record_button = gtk::ToggleButton::builder()
+        .icon_name("media-record-symbolic")
+        .tooltip_text("Record Input")
+        .build();
+    input_list.bind_property("recording", &record_button, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    input_list.connect_recording_finished(clone!(
+        #[weak] toast_overlay,
+        move |_, path| {
+            show_toast(&toast_overlay, &format!("Saved recording to {}", path));
+        }
+    ));
+
+    // Recent input is always kept, so it can be saved after something interesting happens
+    let history_spin_button = gtk::SpinButton::with_range(5.0, 600.0, 5.0);
+    input_list.bind_property("history-seconds", &history_spin_button, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let history_settings = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    history_settings.append(&gtk::Label::new(Some("Keep the last")));
+    history_settings.append(&history_spin_button);
+    history_settings.append(&gtk::Label::new(Some("seconds")));
+
+    // Recordings and saved history are written in the same format
+    let recording_format_dropdown = DropDown::from_strings(&RecordingFormat::ALL.map(|format| format.name()));
+    input_list.bind_property("recording-format", &recording_format_dropdown, "selected")
+        .transform_to(|_, format: RecordingFormat| Some(format.position()))
+        .transform_from(|_, position: u32| RecordingFormat::ALL.get(position as usize).copied())
+        .bidirectional()
+        .sync_create()
+        .build();
+    let recording_format_settings = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    recording_format_settings.append(&gtk::Label::new(Some("Save as")));
+    recording_format_settings.append(&recording_format_dropdown);
+    let save_settings = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(6)
+        .build();
+    save_settings.append(&history_settings);
+    save_settings.append(&recording_format_settings);
+    let save_history_button = adw::SplitButton::builder()
+        .icon_name("document-save-symbolic")
+        .tooltip_text("Save Recent Input")
+        .popover(&gtk::Popover::builder().child(&save_settings).build())
+        .build();
+    save_history_button.connect_clicked(clone!(
+        #[weak] input_list,
+        #[weak] toast_overlay,
+        move |_| {
+            if let Err(err) = input_list.save_history() {
+                show_toast(&toast_overlay, &err.to_string());
+            }
+        }
+    ));
 
     // Use another dropdown to select color schemes
     let colorscheme_list = default_color_schemes();
@@ -99,7 +288,282 @@ fn build_ui(app: &adw::Application) {
         ))
         .build();
 
-    colorscheme_dropdown.bind_property("selected_item", &visualizer, "palette")
+    colorscheme_dropdown.bind_property("selected_item", &layout, "palette")
+        .sync_create()
+        .build();
+
+    // Freezing the visualizers leaves the input running, unlike pausing playback
+    let freeze_button = gtk::ToggleButton::builder()
+        .icon_name("media-playback-pause-symbolic")
+        .tooltip_text("Freeze Visualizers")
+        .build();
+    layout.bind_property("paused", &freeze_button, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+
+    // Grid lines make it easier to read values off the axes
+    let grid_button = gtk::ToggleButton::builder()
+        .icon_name("view-grid-symbolic")
+        .tooltip_text("Show Grid")
+        .build();
+    layout.bind_property("show-grid", &grid_button, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+
+    // The range of levels spanned by the palette, which quiet recordings and loud music need quite different values for
+    let min_db_spin_button = gtk::SpinButton::with_range(-160.0, 0.0, 5.0);
+    layout.bind_property("min-db", &min_db_spin_button, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let max_db_spin_button = gtk::SpinButton::with_range(-160.0, 0.0, 5.0);
+    layout.bind_property("max-db", &max_db_spin_button, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    // Each spin button stops short of the other's value, so the floor stays below the ceiling
+    min_db_spin_button.connect_value_changed(clone!(
+        #[weak] max_db_spin_button,
+        move |min_db_spin_button| max_db_spin_button.adjustment().set_lower(min_db_spin_button.value() + MIN_DB_SPAN as f64)
+    ));
+    max_db_spin_button.connect_value_changed(clone!(
+        #[weak] min_db_spin_button,
+        move |max_db_spin_button| min_db_spin_button.adjustment().set_upper(max_db_spin_button.value() - MIN_DB_SPAN as f64)
+    ));
+    min_db_spin_button.adjustment().set_upper(max_db_spin_button.value() - MIN_DB_SPAN as f64);
+    max_db_spin_button.adjustment().set_lower(min_db_spin_button.value() + MIN_DB_SPAN as f64);
+    let auto_range_switch = gtk::Switch::builder()
+        .halign(gtk::Align::Start)
+        .build();
+    layout.bind_property("auto-range", &auto_range_switch, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    for spin_button in [&min_db_spin_button, &max_db_spin_button] {
+        layout.bind_property("auto-range", spin_button, "sensitive")
+            .invert_boolean()
+            .sync_create()
+            .build();
+    }
+    let level_settings = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(6)
+        .build();
+    for (row, (label, control)) in [
+        ("Floor (dB)", min_db_spin_button.upcast_ref::<gtk::Widget>()),
+        ("Ceiling (dB)", max_db_spin_button.upcast_ref()),
+        ("Automatic", auto_range_switch.upcast_ref()),
+    ].into_iter().enumerate() {
+        level_settings.attach(&gtk::Label::builder().label(label).xalign(0.0).build(), 0, row as i32, 1, 1);
+        level_settings.attach(control, 1, row as i32, 1, 1);
+    }
+    let level_button = gtk::MenuButton::builder()
+        .icon_name("display-brightness-symbolic")
+        .tooltip_text("Level Range")
+        .popover(&gtk::Popover::builder().child(&level_settings).build())
+        .build();
+
+    // A long duration gives an overview, while a short one with a high frame rate shows transients in detail
+    let duration_spin_button = gtk::SpinButton::with_range(0.1, 600.0, 0.5);
+    duration_spin_button.set_digits(1);
+    layout.bind_property("duration", &duration_spin_button, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let frame_rate_spin_button = gtk::SpinButton::with_range(10.0, 4000.0, 50.0);
+    layout.bind_property("frame-rate", &frame_rate_spin_button, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let orientation_dropdown = DropDown::from_strings(&SpectrogramOrientation::ALL.map(|orientation| orientation.name()));
+    layout.bind_property("orientation", &orientation_dropdown, "selected")
+        .transform_to(|_, orientation: SpectrogramOrientation| Some(orientation.position()))
+        .transform_from(|_, position: u32| SpectrogramOrientation::ALL.get(position as usize).copied())
+        .bidirectional()
+        .sync_create()
+        .build();
+    let time_settings = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(6)
+        .build();
+    for (row, (label, control)) in [
+        ("Duration (s)", duration_spin_button.upcast_ref::<gtk::Widget>()),
+        ("Frames per second", frame_rate_spin_button.upcast_ref()),
+        ("Scroll direction", orientation_dropdown.upcast_ref()),
+    ].into_iter().enumerate() {
+        time_settings.attach(&gtk::Label::builder().label(label).xalign(0.0).build(), 0, row as i32, 1, 1);
+        time_settings.attach(control, 1, row as i32, 1, 1);
+    }
+    let time_button = gtk::MenuButton::builder()
+        .icon_name("preferences-system-time-symbolic")
+        .tooltip_text("Time Range")
+        .popover(&gtk::Popover::builder().child(&time_settings).build())
+        .build();
+
+    // Averaging steadies a noisy spectrum, while the hold traces keep track of what's gone past
+    let spectrum_style_dropdown = DropDown::from_strings(&SpectrumStyle::ALL.map(|style| style.name()));
+    layout.bind_property("spectrum-style", &spectrum_style_dropdown, "selected")
+        .transform_to(|_, style: SpectrumStyle| Some(style.position()))
+        .transform_from(|_, position: u32| SpectrumStyle::ALL.get(position as usize).copied())
+        .bidirectional()
+        .sync_create()
+        .build();
+    let averaging_dropdown = DropDown::from_strings(&SpectrumAveraging::ALL.map(|averaging| averaging.name()));
+    layout.bind_property("averaging", &averaging_dropdown, "selected")
+        .transform_to(|_, averaging: SpectrumAveraging| Some(averaging.position()))
+        .transform_from(|_, position: u32| SpectrumAveraging::ALL.get(position as usize).copied())
+        .bidirectional()
+        .sync_create()
+        .build();
+    let averaging_time_spin_button = gtk::SpinButton::with_range(0.01, 10.0, 0.05);
+    averaging_time_spin_button.set_digits(2);
+    layout.bind_property("averaging-time", &averaging_time_spin_button, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let peak_hold_switch = gtk::Switch::builder()
+        .halign(gtk::Align::Start)
+        .build();
+    layout.bind_property("peak-hold", &peak_hold_switch, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let peak_decay_spin_button = gtk::SpinButton::with_range(0.0, 200.0, 5.0);
+    layout.bind_property("peak-decay", &peak_decay_spin_button, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let max_hold_switch = gtk::Switch::builder()
+        .halign(gtk::Align::Start)
+        .build();
+    layout.bind_property("max-hold", &max_hold_switch, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let reset_max_hold_button = gtk::Button::with_label("Reset");
+    reset_max_hold_button.connect_clicked(clone!(
+        #[weak] layout,
+        move |_| {
+            if let Some(visualizer) = layout.active_visualizer() {
+                visualizer.activate_action("spectrum.reset-max-hold", None).ok();
+            }
+        }
+    ));
+    let spectrum_settings = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(6)
+        .build();
+    for (row, (label, control)) in [
+        ("Style", spectrum_style_dropdown.upcast_ref::<gtk::Widget>()),
+        ("Averaging", averaging_dropdown.upcast_ref()),
+        ("Averaging time (s)", averaging_time_spin_button.upcast_ref()),
+        ("Peak hold", peak_hold_switch.upcast_ref()),
+        ("Peak decay (dB/s)", peak_decay_spin_button.upcast_ref()),
+        ("Max hold", max_hold_switch.upcast_ref()),
+        ("", reset_max_hold_button.upcast_ref()),
+    ].into_iter().enumerate() {
+        spectrum_settings.attach(&gtk::Label::builder().label(label).xalign(0.0).build(), 0, row as i32, 1, 1);
+        spectrum_settings.attach(control, 1, row as i32, 1, 1);
+    }
+    let spectrum_button = gtk::MenuButton::builder()
+        .icon_name("view-continuous-symbolic")
+        .tooltip_text("Spectrum Analyzer")
+        .popover(&gtk::Popover::builder().child(&spectrum_settings).build())
+        .build();
+
+    // Visualizers with a zoomable frequency axis provide an action to show the full range again
+    let reset_zoom_button = gtk::Button::builder()
+        .icon_name("zoom-original-symbolic")
+        .tooltip_text("Reset Frequency Range")
+        .build();
+    reset_zoom_button.connect_clicked(clone!(
+        #[weak] layout,
+        move |_| {
+            if let Some(visualizer) = layout.active_visualizer() {
+                visualizer.activate_action("spectrogram.reset-frequency-range", None).ok();
+            }
+        }
+    ));
+
+    // Save what the active pane is showing, for reports and bug trackers
+    let snapshot_button = gtk::Button::builder()
+        .icon_name("camera-photo-symbolic")
+        .tooltip_text("Save Snapshot")
+        .build();
+    snapshot_button.connect_clicked(clone!(
+        #[weak] layout,
+        #[weak] toast_overlay,
+        move |_| {
+            let Some(visualizer) = layout.active_visualizer() else { return };
+            let path = default_snapshot_path();
+            match visualizer.save_snapshot(&path) {
+                Ok(()) => show_toast(&toast_overlay, &format!("Saved snapshot to {}", path.display())),
+                Err(err) => show_toast(&toast_overlay, &err.to_string()),
+            }
+        }
+    ));
+
+    // Panes are added after the one which was last clicked, either beside it or in a new row
+    let add_pane_menu = gtk::gio::Menu::new();
+    for (label, action) in [("Add Beside", "layout.add-beside"), ("Add Below", "layout.add-below")] {
+        let section = gtk::gio::Menu::new();
+        for kind in VisualizerKind::ALL {
+            let item = gtk::gio::MenuItem::new(Some(kind.name()), None);
+            item.set_action_and_target_value(Some(action), Some(&kind.id().to_variant()));
+            section.append_item(&item);
+        }
+        add_pane_menu.append_section(Some(label), &section);
+    }
+    let add_pane_button = gtk::MenuButton::builder()
+        .icon_name("list-add-symbolic")
+        .tooltip_text("Add Pane")
+        .menu_model(&add_pane_menu)
+        .build();
+    let layout_actions = gtk::gio::SimpleActionGroup::new();
+    for (name, new_row) in [("add-beside", false), ("add-below", true)] {
+        let add_pane = gtk::gio::SimpleAction::new(name, Some(&String::static_variant_type()));
+        add_pane.connect_activate(clone!(
+            #[weak] layout,
+            move |_, parameter| {
+                let Some(kind) = parameter.and_then(|p| p.str()).and_then(VisualizerKind::from_id) else { return };
+                layout.add_pane(kind, new_row);
+            }
+        ));
+        layout_actions.add_action(&add_pane);
+    }
+    add_pane_button.insert_action_group("layout", Some(&layout_actions));
+    let remove_pane_button = gtk::Button::builder()
+        .icon_name("list-remove-symbolic")
+        .tooltip_text("Remove Pane")
+        .build();
+    layout.bind_property("n-panes", &remove_pane_button, "sensitive")
+        .transform_to(|_, n_panes: u32| Some(n_panes > 1))
+        .sync_create()
+        .build();
+    remove_pane_button.connect_clicked(clone!(
+        #[weak] layout,
+        move |_| layout.remove_active_pane()
+    ));
+
+    // The active pane's visualizer can be swapped for another without touching the rest of the layout
+    let visualizer_dropdown = DropDown::from_strings(&VisualizerKind::ALL.map(|kind| kind.name()));
+    visualizer_dropdown.set_tooltip_text(Some("Visualizer"));
+    layout.bind_property("active-kind", &visualizer_dropdown, "selected")
+        .transform_to(|_, kind: VisualizerKind| Some(kind.position()))
+        .transform_from(|_, position: u32| VisualizerKind::ALL.get(position as usize).copied())
+        .bidirectional()
+        .sync_create()
+        .build();
+
+    // Perceptual scales spread frequencies the way we hear them, rather than the way they're measured
+    let frequency_scale_dropdown = DropDown::from_strings(&FrequencyScale::ALL.map(|scale| scale.name()));
+    frequency_scale_dropdown.set_tooltip_text(Some("Frequency Scale"));
+    layout.bind_property("frequency-scale", &frequency_scale_dropdown, "selected")
+        .transform_to(|_, scale: FrequencyScale| Some(scale.position()))
+        .transform_from(|_, position: u32| FrequencyScale::ALL.get(position as usize).copied())
+        .bidirectional()
         .sync_create()
         .build();
 
@@ -109,7 +573,23 @@ fn build_ui(app: &adw::Application) {
         .css_classes(["flat", "osd"]) // "osd" is also nice here
         .build();
     toolbar.pack_end(&input_dropdown);
+    toolbar.pack_end(&open_button);
+    toolbar.pack_end(&generator_button);
+    toolbar.pack_end(&record_button);
+    toolbar.pack_end(&save_history_button);
+    toolbar.pack_end(&playback_controls);
     toolbar.pack_end(&colorscheme_dropdown);
+    toolbar.pack_start(&add_pane_button);
+    toolbar.pack_start(&remove_pane_button);
+    toolbar.pack_start(&visualizer_dropdown);
+    toolbar.pack_start(&freeze_button);
+    toolbar.pack_start(&grid_button);
+    toolbar.pack_start(&level_button);
+    toolbar.pack_start(&time_button);
+    toolbar.pack_start(&frequency_scale_dropdown);
+    toolbar.pack_start(&spectrum_button);
+    toolbar.pack_start(&reset_zoom_button);
+    toolbar.pack_start(&snapshot_button);
 
     // Only show the toolbar when you hover over it
     let revealer = gtk::Revealer::builder()
@@ -119,22 +599,71 @@ fn build_ui(app: &adw::Application) {
         .valign(Align::Start)
         .build();
     let toolbar_hover_controller = gtk::EventControllerMotion::builder().build();
-    toolbar_hover_controller.connect_enter(clone!(@weak revealer => move |_, _, _| {
-        revealer.set_reveal_child(true);
-    }));
+    toolbar_hover_controller.connect_enter(clone!(
+        #[weak] revealer,
+        move |_, _, _| revealer.set_reveal_child(true)
+    ));
     revealer.add_controller(toolbar_hover_controller);
-    // Hide the toolbar when you hover over the visualizer
+    // Hide the toolbar when you hover over the visualizers
     let visualizer_hover_controller = gtk::EventControllerMotion::builder().build();
-    visualizer_hover_controller.connect_enter(clone!(@weak revealer => move |_, _, _| {
-        revealer.set_reveal_child(false);
-    }));
-    visualizer.add_controller(visualizer_hover_controller);
+    visualizer_hover_controller.connect_enter(clone!(
+        #[weak] revealer,
+        move |_, _, _| revealer.set_reveal_child(false)
+    ));
+    layout.add_controller(visualizer_hover_controller);
+
+    // Show an empty state instead of the visualizer when there's nothing to listen to
+    let retry_button = gtk::Button::builder()
+        .label("Retry")
+        .halign(Align::Center)
+        .css_classes(["pill", "suggested-action"])
+        .build();
+    let empty_page = adw::StatusPage::builder()
+        .icon_name("audio-input-microphone-symbolic")
+        .title("No Audio Input")
+        .description("Connect a microphone or audio interface to get started")
+        .child(&retry_button)
+        .build();
+    let content = gtk::Stack::new();
+    content.add_named(&layout, Some("visualizer"));
+    content.add_named(&empty_page, Some("empty"));
+    let update_page = |content: &gtk::Stack, input_list: &AudioInputListModel| {
+        content.set_visible_child_name(if input_list.n_items() == 0 { "empty" } else { "visualizer" });
+    };
+    update_page(&content, &input_list);
+    input_list.connect_items_changed(clone!(
+        #[weak] content,
+        move |input_list, _, _, _| update_page(&content, input_list)
+    ));
+    retry_button.connect_clicked(clone!(
+        #[weak] input_list,
+        #[weak] toast_overlay,
+        move |_| {
+            if let Err(err) = input_list.refresh() {
+                show_toast(&toast_overlay, &err.to_string());
+            } else if input_list.n_items() == 0 {
+                show_toast(&toast_overlay, "No audio input devices were found");
+            }
+        }
+    ));
 
     // Use an overlay so the toolbar can overlap the content
     let overlay = Overlay::builder()
-        .child(&offloaded_visualizer)
+        .child(&content)
         .build();
     overlay.add_overlay(&revealer);
+    toast_overlay.set_child(Some(&overlay));
+
+    // Start with the source from the command line, or otherwise the default device
+    match initial_source {
+        Some(source) => {
+            input_dropdown.set_selected(gtk::INVALID_LIST_POSITION);
+            if let Err(err) = input_list.set_source(source) {
+                show_toast(&toast_overlay, &err.to_string());
+            }
+        }
+        None => input_dropdown.notify("selected-item"),
+    }
 
     // create a window and set the title
     let window = adw::ApplicationWindow::builder()
@@ -143,9 +672,31 @@ fn build_ui(app: &adw::Application) {
         .default_height(300)
         .default_width(600)
         .decorated(true)
-        .content(&overlay)
+        .content(&toast_overlay)
         .build();
 
+    // Keep the layout for next time
+    window.connect_close_request(clone!(
+        #[weak] layout,
+        #[upgrade_or] glib::Propagation::Proceed,
+        move |_| {
+            if let Err(err) = layout.save() {
+                eprintln!("Failed to save the layout: {}", err);
+            }
+            glib::Propagation::Proceed
+        }
+    ));
+
     // Present window
     window.present();
-}
\ No newline at end of file
+}
+
+fn show_toast(toast_overlay: &adw::ToastOverlay, message: &str) {
+    // Messages often come from system errors, so they shouldn't be parsed as markup
+    toast_overlay.add_toast(
+        adw::Toast::builder()
+            .title(message)
+            .use_markup(false)
+            .build()
+    );
+}
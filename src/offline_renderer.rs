@@ -0,0 +1,274 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use cpal::SampleRate;
+use plotters::coord::ReverseCoordTranslate;
+use plotters::coord::ranged1d::{DefaultFormatting, KeyPointHint, ReversibleRanged};
+use plotters::prelude::*;
+
+use crate::{
+    colorscheme::{ColorScheme, DEFAULT_MAX_DB, DEFAULT_MIN_DB},
+    fourier::{Frequency, FrequencySample, Period, StereoMagnitude},
+    fourier::audio_transform::AudioTransform,
+    fourier::fft::{DEFAULT_WINDOW_PERIOD, FastFourierTransform},
+    fourier::interpolated_frequency_sample::InterpolatedFrequencySample,
+    frequency_scale::{self, FrequencyScale},
+    sources::{AudioInputError, ChannelLayout, file_source::AudioFileDecoder},
+    widgets::axes,
+};
+
+#[derive(Debug)]
+pub enum RenderError {
+    Input(AudioInputError),
+    Empty,
+    Draw(String),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Input(err) => write!(f, "{}", err),
+            RenderError::Empty => write!(f, "The file is too short to analyze"),
+            RenderError::Draw(message) => write!(f, "Failed to draw the spectrogram: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<AudioInputError> for RenderError {
+    fn from(err: AudioInputError) -> Self { RenderError::Input(err) }
+}
+
+/// An entire audio file, decoded into memory as stereo samples.
+pub struct AudioClip {
+    pub samples: Vec<StereoMagnitude>,
+    pub sample_rate: u32,
+}
+
+impl AudioClip {
+    pub fn load(path: &Path) -> Result<Self, AudioInputError> {
+        let mut decoder = AudioFileDecoder::open(path)?;
+        let mut samples = vec![];
+        while let Some(chunk) = decoder.next_chunk()? {
+            // Mono files are duplicated to both channels, the same way live inputs are
+            match decoder.channels() {
+                ChannelLayout::Mono => samples.extend(chunk.iter().map(|s| (*s, *s))),
+                ChannelLayout::Stereo => samples.extend(chunk.chunks_exact(2).map(|f| (f[0], f[1]))),
+            }
+        }
+        Ok(Self { samples, sample_rate: decoder.sample_rate() })
+    }
+
+    /// Length of the clip in seconds
+    pub fn duration(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate as f64
+    }
+}
+
+pub struct RenderOptions {
+    pub width: u32,
+    pub height: u32,
+    pub frequency_range: Range<Frequency>,
+    /// How frequencies are spread up the image, the same way as in the spectrogram widgets
+    pub frequency_scale: FrequencyScale,
+    pub db_range: Range<f32>,
+    pub palette: ColorScheme,
+    /// Length of each FFT window in seconds
+    pub fft_period: Period,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            frequency_range: frequency_scale::MIN_FREQUENCY..frequency_scale::MAX_FREQUENCY,
+            frequency_scale: FrequencyScale::default(),
+            db_range: DEFAULT_MIN_DB..DEFAULT_MAX_DB,
+            palette: ColorScheme::new_mono(colorous::MAGMA, "Magma"),
+            fft_period: DEFAULT_WINDOW_PERIOD,
+        }
+    }
+}
+
+/// Decodes a whole audio file, and saves its spectrogram to a PNG image with labelled axes.
+pub fn render_file(input: &Path, output: &Path, options: &RenderOptions) -> Result<(), RenderError> {
+    let clip = AudioClip::load(input)?;
+    render_clip(&clip, output, options)
+}
+
+pub fn render_clip(clip: &AudioClip, output: &Path, options: &RenderOptions) -> Result<(), RenderError> {
+    let mut fft = FastFourierTransform::new(clip.sample_rate as Frequency, options.fft_period);
+    let window_size = fft.num_input_samples();
+    if clip.samples.len() < window_size { return Err(RenderError::Empty); }
+
+    let foreground = RGBColor(0, 0, 0);
+    let background = options.palette.background();
+    let background = RGBColor(background.r, background.g, background.b);
+
+    let root = BitMapBackend::new(output, (options.width, options.height)).into_drawing_area();
+    root.fill(&WHITE).map_err(draw_error)?;
+
+    let y_range = FrequencyAxis {
+        scale: options.frequency_scale,
+        // Nothing is known above the nyquist frequency, so the axis stops there
+        range: (options.frequency_range.start.max(1.0), options.frequency_range.end.min(clip.sample_rate as Frequency / 2.0)),
+    };
+    let mut chart = ChartBuilder::on(&root)
+        .margin(16)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..clip.duration(), y_range)
+        .map_err(draw_error)?;
+    chart.plotting_area().fill(&background).map_err(draw_error)?;
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .label_style(("sans-serif", 14, &foreground))
+        .axis_style(foreground)
+        .x_desc("Time (s)")
+        .y_desc("Frequency (Hz)")
+        .y_label_formatter(&|f| format!("{:.0}", f))
+        .draw()
+        .map_err(draw_error)?;
+
+    // Each column of the plot gets its own FFT, centered on the time that column represents
+    let coordinates = chart.as_coord_spec().clone();
+    let (x_pixels, y_pixels) = chart.plotting_area().get_pixel_range();
+    for px in x_pixels.clone() {
+        let Some((time, _)) = coordinates.reverse_translate((px, y_pixels.start)) else { continue };
+        let center = (time * clip.sample_rate as f64) as usize;
+        let start = center.saturating_sub(window_size / 2).min(clip.samples.len() - window_size);
+        let Some(magnitudes) = fft.process(&clip.samples[start..start + window_size]) else { continue };
+        let frequency_sample = InterpolatedFrequencySample::new(magnitudes, SampleRate(clip.sample_rate));
+
+        for py in y_pixels.clone() {
+            // Rows further down the image have lower frequencies
+            let Some((_, f1)) = coordinates.reverse_translate((px, py)) else { continue };
+            let Some((_, f0)) = coordinates.reverse_translate((px, py + 1)) else { continue };
+            let magnitude = frequency_sample.magnitude_in((f0 as Frequency)..(f1 as Frequency));
+
+            // Stereo palettes use opacity for magnitude, so they're blended over the background
+            let (color, alpha) = options.palette.color_in_range(magnitude, options.db_range.clone());
+            let alpha = alpha.clamp(0.0, 1.0) as f64;
+            let blend = |c: u8, b: u8| (c as f64 * alpha + b as f64 * (1.0 - alpha)) as u8;
+            root.draw_pixel(
+                (px, py),
+                &RGBColor(blend(color.r, background.0), blend(color.g, background.1), blend(color.b, background.2)),
+            ).map_err(draw_error)?;
+        }
+    }
+
+    root.present().map_err(draw_error)?;
+    Ok(())
+}
+
+/// A plot axis which places frequencies with a [FrequencyScale], labelled like the spectrograms' axes.
+#[derive(Clone)]
+struct FrequencyAxis {
+    scale: FrequencyScale,
+    range: (Frequency, Frequency),
+}
+
+impl Ranged for FrequencyAxis {
+    type FormatOption = DefaultFormatting;
+    type ValueType = f64;
+
+    fn map(&self, value: &f64, (start, end): (i32, i32)) -> i32 {
+        start + (self.scale.map(*value as Frequency, self.range) as f64 * (end - start) as f64).round() as i32
+    }
+
+    fn key_points<Hint: KeyPointHint>(&self, hint: Hint) -> Vec<f64> {
+        // Points which would crowd the previous label are skipped, since some scales bunch them up
+        let max_points = hint.max_num_points().max(1);
+        let mut last = f32::NEG_INFINITY;
+        axes::frequency_key_points(self.scale, self.range, max_points).into_iter()
+            .filter(|frequency| {
+                let position = self.scale.map(*frequency as Frequency, self.range);
+                let keep = position - last >= 1.0 / max_points as f32;
+                if keep { last = position; }
+                keep
+            })
+            .collect()
+    }
+
+    fn range(&self) -> Range<f64> {
+        self.range.0 as f64..self.range.1 as f64
+    }
+}
+
+impl ReversibleRanged for FrequencyAxis {
+    fn unmap(&self, input: i32, (start, end): (i32, i32)) -> Option<f64> {
+        if start == end { return None; }
+        let fraction = (input - start) as f32 / (end - start) as f32;
+        Some(self.scale.unmap(fraction, self.range) as f64)
+    }
+}
+
+/// Averages the power spectrum of every (non-overlapping) FFT window in the clip.
+///
+/// Returns the center frequency of each bin, along with its average magnitude in each channel.
+pub fn average_spectrum(clip: &AudioClip, fft_period: Period) -> Result<Vec<(Frequency, StereoMagnitude)>, RenderError> {
+    let mut fft = FastFourierTransform::new(clip.sample_rate as Frequency, fft_period);
+    let window_size = fft.num_input_samples();
+
+    let mut power: Vec<(f32, f32)> = vec![];
+    let mut num_windows = 0;
+    for window in clip.samples.chunks_exact(window_size) {
+        let Some(magnitudes) = fft.process(window) else { continue };
+        power.resize(magnitudes.len(), (0.0, 0.0));
+        for ((l, r), (ml, mr)) in power.iter_mut().zip(magnitudes) {
+            *l += ml * ml;
+            *r += mr * mr;
+        }
+        num_windows += 1;
+    }
+    if num_windows == 0 { return Err(RenderError::Empty); }
+
+    // Bins are spaced the same way as in `InterpolatedFrequencySample`
+    let bin_width = clip.sample_rate as Frequency / (2 * power.len()) as Frequency;
+    Ok(
+        power.iter()
+            .enumerate()
+            .map(|(i, (l, r))| (
+                i as Frequency * bin_width,
+                ((l / num_windows as f32).sqrt(), (r / num_windows as f32).sqrt()),
+            ))
+            .collect()
+    )
+}
+
+/// Combines the bins of a spectrum into bands which are evenly spaced on a frequency scale.
+///
+/// Returns the middle frequency of each band, along with the average power of its bins as a magnitude.
+/// Bands narrower than a bin take the bin nearest their middle.
+pub fn scaled_spectrum(
+    spectrum: &[(Frequency, StereoMagnitude)],
+    scale: FrequencyScale,
+    range: (Frequency, Frequency),
+    bands: usize,
+) -> Vec<(Frequency, StereoMagnitude)> {
+    (0..bands)
+        .map(|band| {
+            let edge = |fraction: f32| scale.unmap(fraction / bands as f32, range);
+            let (low, middle, high) = (edge(band as f32), edge(band as f32 + 0.5), edge(band as f32 + 1.0));
+            let bins: Vec<_> = spectrum.iter().filter(|(f, _)| (low..high).contains(f)).collect();
+            let magnitude = if bins.is_empty() {
+                spectrum.iter()
+                    .min_by(|(a, _), (b, _)| (a - middle).abs().total_cmp(&(b - middle).abs()))
+                    .map_or((0.0, 0.0), |(_, magnitude)| *magnitude)
+            } else {
+                let count = bins.len() as f32;
+                let (l, r) = bins.iter().fold((0.0, 0.0), |(l, r), (_, (ml, mr))| (l + ml * ml, r + mr * mr));
+                ((l / count).sqrt(), (r / count).sqrt())
+            };
+            (middle, magnitude)
+        })
+        .collect()
+}
+
+fn draw_error(err: impl std::error::Error) -> RenderError {
+    RenderError::Draw(err.to_string())
+}
@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use gtk::glib;
+
+/// Chooses a new file in one of the user's directories, named after the current time.
+///
+/// Timestamps only go down to the second, so a number is added if a file with that name already exists.
+pub fn timestamped_path(directory: glib::UserDirectory, extension: &str) -> PathBuf {
+    let directory = glib::user_special_dir(directory)
+        .unwrap_or_else(glib::home_dir);
+    let timestamp = glib::DateTime::now_local()
+        .and_then(|now| now.format("%Y-%m-%d %H-%M-%S"))
+        .map(|timestamp| timestamp.to_string())
+        .unwrap_or_default();
+    (1..)
+        .map(|copy| match copy {
+            1 => directory.join(format!("Spectrogram {}.{}", timestamp, extension)),
+            copy => directory.join(format!("Spectrogram {} ({}).{}", timestamp, copy, extension)),
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
@@ -0,0 +1,80 @@
+use std::rc::Rc;
+use std::time::Duration;
+use cpal::{Device, InputCallbackInfo, Stream, StreamConfig, StreamInstant};
+use cpal::traits::{DeviceTrait, StreamTrait};
+
+use crate::sources::{AudioInputError, AudioSource, ChannelLayout, SampleProducer};
+
+/// Live input from a device discovered by cpal.
+pub struct CpalSource {
+    device: Rc<Device>,
+    config: StreamConfig,
+    channels: ChannelLayout,
+    stream: Option<Stream>,
+}
+
+impl CpalSource {
+    pub fn new(device: Rc<Device>) -> Result<Self, AudioInputError> {
+        let config: StreamConfig = device.default_input_config()?.config();
+        let channels = ChannelLayout::from_count(config.channels)?;
+        Ok(Self {
+            device,
+            config,
+            channels,
+            stream: None,
+        })
+    }
+}
+
+impl AudioSource for CpalSource {
+    fn name(&self) -> String {
+        self.device.name().unwrap_or_default()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate.0
+    }
+
+    fn channels(&self) -> ChannelLayout {
+        self.channels
+    }
+
+    fn start(&mut self, producer: SampleProducer) -> Result<(), AudioInputError> {
+        let channels = self.channels;
+        let sample_rate = self.config.sample_rate;
+        let error_producer = producer.clone();
+        let mut expected_capture: Option<StreamInstant> = None;
+        let stream = self.device.build_input_stream(
+            &self.config,
+            move |data: &[f32], info: &InputCallbackInfo| {
+                let num_samples = data.len() / channels.count() as usize;
+
+                // If this buffer starts noticeably later than the previous one ended, the device lost samples
+                let capture = info.timestamp().capture;
+                let buffer_duration = Duration::from_secs_f64(num_samples as f64 / sample_rate.0 as f64);
+                if let Some(gap) = expected_capture.and_then(|expected| capture.duration_since(&expected)) {
+                    if gap > buffer_duration / 2 {
+                        producer.report_gap();
+                    }
+                }
+                expected_capture = capture.add(buffer_duration);
+
+                producer.push_interleaved(data, channels);
+            },
+            move |err| error_producer.report_error(err.to_string()),
+            None,
+        )?;
+
+        // Start the newly created stream (usually not necessary)
+        stream.play()?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // Dropping the stream releases the device, even if pausing isn't supported
+        if let Some(stream) = self.stream.take() {
+            stream.pause().ok();
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{JoinHandle, sleep};
+use std::time::{Duration, Instant};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+    units::{Time, TimeBase},
+};
+
+use crate::sources::{AudioInputError, AudioSource, ChannelLayout, SampleProducer};
+
+// Decoded packets are split into chunks this size, so that pacing is smooth and the ring buffer isn't flooded
+const CHUNK_FRAMES: usize = 512;
+// Seeks stop this far short of the end, since formats can fail to seek to (or past) their last packet
+const SEEK_END_MARGIN: f64 = 0.01;
+
+/// Decodes an audio file into interleaved mono or stereo samples.
+///
+/// Files with more than two channels are reduced to their first two (front left & right).
+pub struct AudioFileDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    sample_rate: u32,
+    channels: ChannelLayout,
+    duration: Option<f64>,
+}
+
+impl AudioFileDecoder {
+    pub fn open(path: &Path) -> Result<Self, AudioInputError> {
+        let file = File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+        // The extension helps the probe pick the right container format
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let format = probed.format;
+
+        let track = format.tracks().iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(AudioInputError::Decode(SymphoniaError::Unsupported("no audio track")))?;
+        let params = &track.codec_params;
+        let sample_rate = params.sample_rate
+            .ok_or(AudioInputError::Decode(SymphoniaError::Unsupported("unknown sample rate")))?;
+        let channels = match params.channels.map(|c| c.count()).unwrap_or(0) {
+            0 => return Err(AudioInputError::UnsupportedChannels(0)),
+            1 => ChannelLayout::Mono,
+            _ => ChannelLayout::Stereo,
+        };
+        let duration = params.n_frames.map(|frames| frames as f64 / sample_rate as f64);
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+
+        Ok(Self {
+            track_id: track.id,
+            time_base: params.time_base,
+            format,
+            decoder,
+            sample_rate,
+            channels,
+            duration,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    pub fn channels(&self) -> ChannelLayout { self.channels }
+
+    /// Length of the file in seconds, if the container reports it
+    pub fn duration(&self) -> Option<f64> { self.duration }
+
+    /// Decodes the next packet into interleaved samples, or returns `None` at the end of the file.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<f32>>, AudioInputError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                // Symphonia reports the end of the file as an IO error
+                Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            if packet.track_id() != self.track_id { continue; }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // Corrupt packets are skipped, rather than ending playback
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let spec = *decoded.spec();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+
+            let source_channels = spec.channels.count();
+            let samples = match self.channels {
+                ChannelLayout::Mono => buffer.samples().to_vec(),
+                ChannelLayout::Stereo => buffer.samples()
+                    .chunks_exact(source_channels)
+                    .flat_map(|frame| [frame[0], frame[1]])
+                    .collect(),
+            };
+            return Ok(Some(samples));
+        }
+    }
+
+    /// Seeks to a time in seconds, returning the position which was actually reached.
+    ///
+    /// Times past the end of a file with a known duration are moved back to just before it.
+    pub fn seek(&mut self, seconds: f64) -> Result<f64, AudioInputError> {
+        let seconds = match self.duration {
+            Some(duration) => seconds.min(duration - SEEK_END_MARGIN),
+            None => seconds,
+        }.max(0.0);
+        let seeked = self.format.seek(SeekMode::Coarse, SeekTo::Time {
+            time: Time::from(seconds),
+            track_id: Some(self.track_id),
+        })?;
+        self.decoder.reset();
+        Ok(match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(seeked.actual_ts);
+                time.seconds as f64 + time.frac
+            }
+            None => seconds,
+        })
+    }
+}
+
+/// Transport state shared between a playing file and its controls.
+#[derive(Default)]
+pub struct FilePlayback {
+    pub paused: AtomicBool,
+    pub looping: AtomicBool,
+    /// When unset, the file is decoded as fast as the visualizers can keep up with
+    pub realtime: AtomicBool,
+    /// Set once playback reaches the end of a file which isn't looping
+    pub finished: AtomicBool,
+    position: AtomicU64,
+    seek_request: Mutex<Option<f64>>,
+    stop_request: AtomicBool,
+}
+
+impl FilePlayback {
+    /// Current playback position in seconds
+    pub fn position(&self) -> f64 {
+        f64::from_bits(self.position.load(Ordering::Relaxed))
+    }
+
+    fn set_position(&self, seconds: f64) {
+        self.position.store(seconds.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn seek(&self, seconds: f64) {
+        *self.seek_request.lock().unwrap() = Some(seconds);
+        self.finished.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Streams an audio file to the visualizers, as if it were a live input.
+pub struct FileSource {
+    path: PathBuf,
+    sample_rate: u32,
+    channels: ChannelLayout,
+    duration: Option<f64>,
+    playback: Arc<FilePlayback>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> Result<Self, AudioInputError> {
+        // Open the file once up-front, so that problems are reported immediately
+        let decoder = AudioFileDecoder::open(path)?;
+        let playback = FilePlayback::default();
+        playback.realtime.store(true, Ordering::Relaxed);
+        Ok(Self {
+            path: path.to_path_buf(),
+            sample_rate: decoder.sample_rate(),
+            channels: decoder.channels(),
+            duration: decoder.duration(),
+            playback: Arc::new(playback),
+            thread: None,
+        })
+    }
+
+    pub fn duration(&self) -> Option<f64> { self.duration }
+
+    pub fn playback(&self) -> Arc<FilePlayback> { Arc::clone(&self.playback) }
+}
+
+impl AudioSource for FileSource {
+    fn name(&self) -> String {
+        self.path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    fn channels(&self) -> ChannelLayout { self.channels }
+
+    fn start(&mut self, producer: SampleProducer) -> Result<(), AudioInputError> {
+        let mut decoder = AudioFileDecoder::open(&self.path)?;
+        let playback = Arc::clone(&self.playback);
+        playback.stop_request.store(false, Ordering::Relaxed);
+        self.thread = Some(std::thread::spawn(move || {
+            if let Err(err) = play(&mut decoder, &playback, &producer) {
+                producer.report_error(err.to_string());
+            }
+        }));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.playback.stop_request.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for FileSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn play(
+    decoder: &mut AudioFileDecoder,
+    playback: &FilePlayback,
+    producer: &SampleProducer,
+) -> Result<(), AudioInputError> {
+    let frame_size = decoder.channels().count() as usize;
+    let sample_rate = decoder.sample_rate() as f64;
+    let mut position = playback.position();
+    if position > 0.0 {
+        position = seek_or_report(decoder, position, 0.0, producer);
+        playback.set_position(position);
+    }
+
+    // Pacing is measured from this point, and restarted whenever playback is interrupted
+    let mut clock_start = Instant::now();
+    let mut clock_frames = 0u64;
+    let mut realtime = playback.realtime.load(Ordering::Relaxed);
+
+    while !playback.stop_request.load(Ordering::Relaxed) {
+        if let Some(seconds) = playback.seek_request.lock().unwrap().take() {
+            position = seek_or_report(decoder, seconds, position, producer);
+            playback.set_position(position);
+            clock_start = Instant::now();
+            clock_frames = 0;
+        }
+        if playback.paused.load(Ordering::Relaxed) || playback.finished.load(Ordering::Relaxed) {
+            sleep(Duration::from_millis(10));
+            clock_start = Instant::now();
+            clock_frames = 0;
+            continue;
+        }
+
+        let Some(samples) = decoder.next_chunk()? else {
+            if playback.looping.load(Ordering::Relaxed) {
+                playback.seek(0.0);
+            } else {
+                playback.finished.store(true, Ordering::Relaxed);
+            }
+            continue;
+        };
+
+        for chunk in samples.chunks(CHUNK_FRAMES * frame_size) {
+            let num_frames = chunk.len() / frame_size;
+            if realtime != playback.realtime.load(Ordering::Relaxed) {
+                realtime = !realtime;
+                clock_start = Instant::now();
+                clock_frames = 0;
+            }
+            if realtime {
+                // Wait until the chunk is due, as if it were arriving from a device
+                let due = clock_start + Duration::from_secs_f64(clock_frames as f64 / sample_rate);
+                if let Some(delay) = due.checked_duration_since(Instant::now()) {
+                    sleep(delay);
+                }
+            } else {
+                // Otherwise, only wait for the visualizers to make room
+                while producer.vacant_len() < num_frames && !playback.stop_request.load(Ordering::Relaxed) {
+                    sleep(Duration::from_millis(1));
+                }
+            }
+            producer.push_interleaved(chunk, decoder.channels());
+            clock_frames += num_frames as u64;
+            position += num_frames as f64 / sample_rate;
+            playback.set_position(position);
+        }
+    }
+    Ok(())
+}
+
+/// Seeks the decoder, or reports why it couldn't and carries on from where it was.
+fn seek_or_report(decoder: &mut AudioFileDecoder, seconds: f64, current: f64, producer: &SampleProducer) -> f64 {
+    match decoder.seek(seconds) {
+        Ok(position) => position,
+        Err(err) => {
+            producer.report_error(format!("Failed to seek: {}", err));
+            current
+        }
+    }
+}
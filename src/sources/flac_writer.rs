@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use flacenc::bitsink::MemSink;
+use flacenc::component::{BitRepr, Stream};
+use flacenc::config;
+use flacenc::error::{Verified, Verify};
+use flacenc::source::{Context, Fill, FrameBuf};
+
+use crate::sources::{AudioInputError, ChannelLayout};
+
+// Samples are rounded to 24 bits, which is finer than any converter's noise floor
+const BITS_PER_SAMPLE: usize = 24;
+const BLOCK_SIZE: usize = 4096;
+
+/// Writes samples to a FLAC file as they arrive, a block at a time.
+///
+/// The header can only be completed once everything has been written (it holds the length and checksum),
+/// so it's written again by `finalize`.
+pub struct FlacWriter {
+    file: BufWriter<File>,
+    stream: Stream,
+    config: Verified<config::Encoder>,
+    block: (FrameBuf, Context),
+    /// Interleaved samples which don't fill a block yet
+    pending: Vec<i32>,
+    block_len: usize,
+    sink: MemSink<u8>,
+}
+
+impl FlacWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: ChannelLayout) -> Result<Self, AudioInputError> {
+        let channels = channels.count() as usize;
+        let config = config::Encoder::default().into_verified().map_err(|(_, err)| err)?;
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            stream: Stream::new(sample_rate as usize, channels, BITS_PER_SAMPLE)?,
+            config,
+            block: (FrameBuf::with_size(channels, BLOCK_SIZE)?, Context::new(BITS_PER_SAMPLE, channels)),
+            pending: Vec::with_capacity(BLOCK_SIZE * channels),
+            block_len: BLOCK_SIZE * channels,
+            sink: MemSink::new(),
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), AudioInputError> {
+        let scale = ((1 << (BITS_PER_SAMPLE - 1)) - 1) as f32;
+        for sample in samples {
+            self.pending.push((sample.clamp(-1.0, 1.0) * scale).round() as i32);
+            if self.pending.len() == self.block_len {
+                self.write_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes whatever is left as a shorter block, then fills in the header.
+    pub fn finalize(mut self) -> Result<(), AudioInputError> {
+        if !self.pending.is_empty() {
+            self.write_block()?;
+        }
+        // Every block is the same size, except for the last one (which the block sizes in the header leave out)
+        let stream_info = self.stream.stream_info_mut();
+        stream_info.set_block_sizes(BLOCK_SIZE, BLOCK_SIZE)?;
+        stream_info.set_md5_digest(&self.block.1.md5_digest());
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// The stream has no frames of its own, so this is just the signature and stream info.
+    fn write_header(&mut self) -> Result<(), AudioInputError> {
+        self.sink.clear();
+        self.stream.write(&mut self.sink)?;
+        self.file.write_all(self.sink.as_slice())?;
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> Result<(), AudioInputError> {
+        self.block.fill_interleaved(&self.pending)?;
+        self.pending.clear();
+        let frame = flacenc::encode_fixed_size_frame(
+            &self.config,
+            &self.block.0,
+            self.block.1.current_frame_number().unwrap_or_default(),
+            self.stream.stream_info(),
+        )?;
+        self.stream.stream_info_mut().update_frame_info(&frame);
+        self.sink.clear();
+        frame.write(&mut self.sink)?;
+        self.file.write_all(self.sink.as_slice())?;
+        Ok(())
+    }
+}
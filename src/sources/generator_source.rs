@@ -0,0 +1,236 @@
+use std::f64::consts::TAU;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{JoinHandle, sleep};
+use std::time::{Duration, Instant};
+
+use crate::sources::{AudioInputError, AudioSource, ChannelLayout, SampleProducer};
+
+const SAMPLE_RATE: u32 = 48000;
+// Matches the chunk size used for files, so pacing is similarly smooth
+const CHUNK_FRAMES: usize = 512;
+
+/// The shape of a generated signal.
+#[derive(Clone, Debug)]
+pub enum Waveform {
+    Silence,
+    Sine { frequency: f64 },
+    /// Several sines of equal amplitude, summed together
+    MultiTone { frequencies: Vec<f64> },
+    /// A sine which glides from one frequency to another, and then starts over
+    Sweep { start: f64, end: f64, seconds: f64, logarithmic: bool },
+    WhiteNoise,
+    PinkNoise,
+    /// Single-sample clicks at a regular rate
+    ImpulseTrain { frequency: f64 },
+}
+
+/// The settings for one channel of a generator.
+#[derive(Clone, Debug)]
+pub struct Signal {
+    pub waveform: Waveform,
+    /// Peak amplitude, where 1.0 is full scale
+    pub amplitude: f32,
+}
+
+impl Signal {
+    pub fn new(waveform: Waveform) -> Self {
+        Self { waveform, amplitude: 0.5 }
+    }
+}
+
+/// A source of synthetic test signals, with independent left & right channels.
+pub struct GeneratorSource {
+    name: String,
+    left: Signal,
+    right: Signal,
+    stop_request: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GeneratorSource {
+    pub fn new(name: &str, left: Signal, right: Signal) -> Self {
+        Self {
+            name: name.into(),
+            left,
+            right,
+            stop_request: Default::default(),
+            thread: None,
+        }
+    }
+
+    /// Produces the same signal in both channels
+    pub fn mono(name: &str, signal: Signal) -> Self {
+        Self::new(name, signal.clone(), signal)
+    }
+
+    /// A selection of signals which are useful for checking the visualizers.
+    pub fn presets() -> Vec<GeneratorSource> {
+        use Waveform::*;
+        let sweep = |logarithmic| Sweep { start: 20.0, end: 20000.0, seconds: 10.0, logarithmic };
+        vec![
+            Self::mono("Sine (440 Hz)", Signal::new(Sine { frequency: 440.0 })),
+            Self::mono("Chord (A major)", Signal::new(MultiTone { frequencies: vec![220.0, 277.18, 329.63] })),
+            Self::mono("Linear Sweep (20 Hz – 20 kHz)", Signal::new(sweep(false))),
+            Self::mono("Logarithmic Sweep (20 Hz – 20 kHz)", Signal::new(sweep(true))),
+            Self::mono("White Noise", Signal::new(WhiteNoise)),
+            Self::mono("Pink Noise", Signal::new(PinkNoise)),
+            Self::mono("Impulse Train (10 Hz)", Signal::new(ImpulseTrain { frequency: 10.0 })),
+            Self::new(
+                "Stereo Sines (440 Hz left, 660 Hz right)",
+                Signal::new(Sine { frequency: 440.0 }),
+                Signal::new(Sine { frequency: 660.0 }),
+            ),
+            Self::new(
+                "Stereo Sweeps (rising left, falling right)",
+                Signal::new(sweep(true)),
+                Signal::new(Sweep { start: 20000.0, end: 20.0, seconds: 10.0, logarithmic: true }),
+            ),
+            Self::new("Left Channel Only (pink noise)", Signal::new(PinkNoise), Signal::new(Silence)),
+        ]
+    }
+}
+
+impl AudioSource for GeneratorSource {
+    fn name(&self) -> String { self.name.clone() }
+
+    fn sample_rate(&self) -> u32 { SAMPLE_RATE }
+
+    fn channels(&self) -> ChannelLayout { ChannelLayout::Stereo }
+
+    fn start(&mut self, producer: SampleProducer) -> Result<(), AudioInputError> {
+        // Each channel gets a different seed, so that stereo noise isn't correlated
+        let mut left = Oscillator::new(self.left.clone(), 0x9E3779B97F4A7C15);
+        let mut right = Oscillator::new(self.right.clone(), 0xD1B54A32D192ED03);
+        let stop_request = Arc::clone(&self.stop_request);
+        stop_request.store(false, Ordering::Relaxed);
+        self.thread = Some(std::thread::spawn(move || {
+            let clock_start = Instant::now();
+            let mut clock_frames = 0u64;
+            let mut chunk = Vec::with_capacity(CHUNK_FRAMES * 2);
+            while !stop_request.load(Ordering::Relaxed) {
+                chunk.clear();
+                for _ in 0..CHUNK_FRAMES {
+                    chunk.push(left.next_sample());
+                    chunk.push(right.next_sample());
+                }
+
+                // Wait until the chunk is due, as if it were arriving from a device
+                let due = clock_start + Duration::from_secs_f64(clock_frames as f64 / SAMPLE_RATE as f64);
+                if let Some(delay) = due.checked_duration_since(Instant::now()) {
+                    sleep(delay);
+                }
+                producer.push_interleaved(&chunk, ChannelLayout::Stereo);
+                clock_frames += CHUNK_FRAMES as u64;
+            }
+        }));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop_request.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for GeneratorSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Generates one channel of a [Signal], one sample at a time.
+struct Oscillator {
+    signal: Signal,
+    /// One phase per tone, measured in cycles
+    phases: Vec<f64>,
+    /// Seconds since the start of the current sweep
+    time: f64,
+    rng: u64,
+    pink_state: [f32; 7],
+}
+
+impl Oscillator {
+    fn new(signal: Signal, seed: u64) -> Self {
+        let num_phases = match &signal.waveform {
+            Waveform::MultiTone { frequencies } => frequencies.len(),
+            _ => 1,
+        };
+        Self {
+            signal,
+            phases: vec![0.0; num_phases],
+            time: 0.0,
+            rng: seed,
+            pink_state: [0.0; 7],
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let dt = 1.0 / SAMPLE_RATE as f64;
+        let value = match &self.signal.waveform {
+            Waveform::Silence => 0.0,
+            Waveform::Sine { frequency } => {
+                let value = (self.phases[0] * TAU).sin();
+                self.phases[0] = (self.phases[0] + frequency * dt).fract();
+                value as f32
+            }
+            Waveform::MultiTone { frequencies } => {
+                let mut sum = 0.0;
+                for (phase, frequency) in self.phases.iter_mut().zip(frequencies) {
+                    sum += (*phase * TAU).sin();
+                    *phase = (*phase + frequency * dt).fract();
+                }
+                (sum / frequencies.len().max(1) as f64) as f32
+            }
+            Waveform::Sweep { start, end, seconds, logarithmic } => {
+                let progress = self.time / seconds;
+                let frequency = if *logarithmic {
+                    start * (end / start).powf(progress)
+                } else {
+                    start + (end - start) * progress
+                };
+                let value = (self.phases[0] * TAU).sin();
+                self.phases[0] = (self.phases[0] + frequency * dt).fract();
+                self.time = (self.time + dt) % seconds;
+                value as f32
+            }
+            Waveform::WhiteNoise => white_noise(&mut self.rng),
+            Waveform::PinkNoise => pink_noise(&mut self.rng, &mut self.pink_state),
+            Waveform::ImpulseTrain { frequency } => {
+                // Click whenever the phase wraps around, keeping the remainder so the rate stays exact
+                self.phases[0] += frequency * dt;
+                if self.phases[0] >= 1.0 {
+                    self.phases[0] -= 1.0;
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        };
+        value * self.signal.amplitude
+    }
+}
+
+/// Uniform noise in the range -1..1, from a xorshift generator
+fn white_noise(rng: &mut u64) -> f32 {
+    *rng ^= *rng << 13;
+    *rng ^= *rng >> 7;
+    *rng ^= *rng << 17;
+    (*rng >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0
+}
+
+fn pink_noise(rng: &mut u64, b: &mut [f32; 7]) -> f32 {
+    // Paul Kellet's filter, which approximates a -3dB/octave slope across the audible range
+    let white = white_noise(rng);
+    b[0] = 0.99886 * b[0] + white * 0.0555179;
+    b[1] = 0.99332 * b[1] + white * 0.0750759;
+    b[2] = 0.96900 * b[2] + white * 0.1538520;
+    b[3] = 0.86650 * b[3] + white * 0.3104856;
+    b[4] = 0.55000 * b[4] + white * 0.5329522;
+    b[5] = -0.7616 * b[5] - white * 0.0168980;
+    let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+    b[6] = white * 0.115926;
+    pink * 0.11
+}
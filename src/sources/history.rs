@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::sources::{AudioInputError, ChannelLayout, SampleTap, StreamEvent, recorder::{RecordingFormat, RecordingWriter}};
+
+/// Keeps the most recent stretch of raw input, so that it can be saved after the fact.
+pub struct SampleHistory {
+    buffer: Mutex<HistoryBuffer>,
+}
+
+struct HistoryBuffer {
+    samples: VecDeque<f32>,
+    seconds: f64,
+    sample_rate: u32,
+    channels: ChannelLayout,
+}
+
+impl HistoryBuffer {
+    fn capacity(&self) -> usize {
+        (self.seconds * self.sample_rate as f64) as usize * self.channels.count() as usize
+    }
+
+    /// Discards the oldest samples, leaving room for `incoming` more.
+    fn trim(&mut self, incoming: usize) {
+        let excess = (self.samples.len() + incoming).saturating_sub(self.capacity());
+        self.samples.drain(..excess.min(self.samples.len()));
+    }
+
+    /// Allocates the whole history up-front, so that the audio thread never has to grow it.
+    fn reserve(&mut self) {
+        let capacity = self.capacity();
+        self.samples.reserve(capacity.saturating_sub(self.samples.len()));
+        self.samples.shrink_to(capacity);
+    }
+}
+
+impl SampleHistory {
+    pub fn new(seconds: f64) -> Self {
+        Self {
+            buffer: Mutex::new(HistoryBuffer {
+                samples: VecDeque::new(),
+                seconds,
+                sample_rate: 0,
+                channels: ChannelLayout::Mono,
+            }),
+        }
+    }
+
+    /// Discards the history, and prepares to receive samples in a new format.
+    pub fn reset(&self, sample_rate: u32, channels: ChannelLayout) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.samples.clear();
+        buffer.sample_rate = sample_rate;
+        buffer.channels = channels;
+        buffer.reserve();
+    }
+
+    pub fn set_seconds(&self, seconds: f64) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.seconds = seconds;
+        buffer.trim(0);
+        buffer.reserve();
+    }
+
+    /// Starts writing everything currently in the history to a file, leaving the history as it was.
+    ///
+    /// A long history can take a while to encode, so it's written on another thread (like a recording),
+    /// which reports `Saved` or `Error` to `events` once it's done.
+    pub fn save(
+        &self,
+        path: PathBuf,
+        format: RecordingFormat,
+        events: async_channel::Sender<StreamEvent>,
+    ) -> Result<(), AudioInputError> {
+        let (samples, sample_rate, channels) = self.snapshot()?;
+        // The file is created straight away, so the next save picks a different name
+        let mut writer = RecordingWriter::create(&path, format, sample_rate, channels)?;
+        std::thread::spawn(move || {
+            let event = match writer.write_samples(&samples).and_then(|()| writer.finalize()) {
+                Ok(()) => StreamEvent::Saved(path),
+                Err(err) => StreamEvent::Error(format!("Failed to save the recent input: {}", err)),
+            };
+            events.send_blocking(event).ok();
+        });
+        Ok(())
+    }
+
+    /// Copies the history, along with its format.
+    ///
+    /// The copy is allocated before taking the lock, so the audio thread is only held up for as long as the copy takes.
+    fn snapshot(&self) -> Result<(Vec<f32>, u32, ChannelLayout), AudioInputError> {
+        let capacity = self.buffer.lock().unwrap().capacity();
+        let mut samples = Vec::with_capacity(capacity);
+        let buffer = self.buffer.lock().unwrap();
+        if buffer.samples.is_empty() { return Err(AudioInputError::NothingCaptured); }
+        let (front, back) = buffer.samples.as_slices();
+        samples.extend_from_slice(front);
+        samples.extend_from_slice(back);
+        Ok((samples, buffer.sample_rate, buffer.channels))
+    }
+}
+
+/// Feeds the input into a [SampleHistory].
+pub struct HistoryTap(pub Arc<SampleHistory>);
+
+impl SampleTap for HistoryTap {
+    fn write(&mut self, data: &[f32], layout: ChannelLayout) {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        if layout != buffer.channels { return; }
+        // Only the end of a very large chunk could be kept anyway
+        let start = data.len().saturating_sub(buffer.capacity());
+        buffer.trim(data.len() - start);
+        buffer.samples.extend(&data[start..]);
+    }
+}
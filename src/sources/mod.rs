@@ -0,0 +1,332 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use cpal::ChannelCount;
+use ringbuf::{HeapProd, traits::{Observer, Producer}};
+use itertools::Itertools;
+
+use crate::fourier::StereoMagnitude;
+
+pub mod cpal_source;
+pub mod file_source;
+pub mod flac_writer;
+pub mod generator_source;
+pub mod history;
+pub mod network_source;
+pub mod pipe_source;
+pub mod recorder;
+
+#[derive(Debug)]
+pub enum AudioInputError {
+    NoDevice,
+    NothingCaptured,
+    UnsupportedChannels(ChannelCount),
+    Devices(cpal::DevicesError),
+    Config(cpal::DefaultStreamConfigError),
+    Build(cpal::BuildStreamError),
+    Play(cpal::PlayStreamError),
+    Io(std::io::Error),
+    Decode(symphonia::core::errors::Error),
+    Record(hound::Error),
+    Encode(Box<dyn std::error::Error>),
+}
+
+impl fmt::Display for AudioInputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioInputError::NoDevice => write!(f, "No audio input device is available"),
+            AudioInputError::NothingCaptured => write!(f, "Nothing has been captured yet"),
+            AudioInputError::UnsupportedChannels(channels) =>
+                write!(f, "{}-channel input is not supported", channels),
+            AudioInputError::Devices(err) => write!(f, "Failed to list input devices: {}", err),
+            AudioInputError::Config(err) => write!(f, "Failed to configure input device: {}", err),
+            AudioInputError::Build(err) => write!(f, "Failed to open input stream: {}", err),
+            AudioInputError::Play(err) => write!(f, "Failed to start input stream: {}", err),
+            AudioInputError::Io(err) => write!(f, "Failed to read input: {}", err),
+            AudioInputError::Decode(err) => write!(f, "Failed to decode input: {}", err),
+            AudioInputError::Record(err) => write!(f, "Failed to write recording: {}", err),
+            AudioInputError::Encode(err) => write!(f, "Failed to encode recording: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AudioInputError {}
+
+impl From<cpal::DevicesError> for AudioInputError {
+    fn from(err: cpal::DevicesError) -> Self { AudioInputError::Devices(err) }
+}
+
+impl From<cpal::DefaultStreamConfigError> for AudioInputError {
+    fn from(err: cpal::DefaultStreamConfigError) -> Self { AudioInputError::Config(err) }
+}
+
+impl From<cpal::BuildStreamError> for AudioInputError {
+    fn from(err: cpal::BuildStreamError) -> Self { AudioInputError::Build(err) }
+}
+
+impl From<cpal::PlayStreamError> for AudioInputError {
+    fn from(err: cpal::PlayStreamError) -> Self { AudioInputError::Play(err) }
+}
+
+impl From<std::io::Error> for AudioInputError {
+    fn from(err: std::io::Error) -> Self { AudioInputError::Io(err) }
+}
+
+impl From<symphonia::core::errors::Error> for AudioInputError {
+    fn from(err: symphonia::core::errors::Error) -> Self { AudioInputError::Decode(err) }
+}
+
+impl From<hound::Error> for AudioInputError {
+    fn from(err: hound::Error) -> Self { AudioInputError::Record(err) }
+}
+
+impl From<flacenc::error::EncodeError> for AudioInputError {
+    fn from(err: flacenc::error::EncodeError) -> Self { AudioInputError::Encode(Box::new(err)) }
+}
+
+impl From<flacenc::error::SourceError> for AudioInputError {
+    fn from(err: flacenc::error::SourceError) -> Self { AudioInputError::Encode(Box::new(err)) }
+}
+
+impl From<flacenc::error::VerifyError> for AudioInputError {
+    fn from(err: flacenc::error::VerifyError) -> Self { AudioInputError::Encode(Box::new(err)) }
+}
+
+impl From<flacenc::error::OutputError<flacenc::bitsink::MemSink<u8>>> for AudioInputError {
+    fn from(err: flacenc::error::OutputError<flacenc::bitsink::MemSink<u8>>) -> Self {
+        AudioInputError::Encode(Box::new(err))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+}
+
+impl ChannelLayout {
+    pub fn from_count(channels: ChannelCount) -> Result<Self, AudioInputError> {
+        match channels {
+            1 => Ok(ChannelLayout::Mono),
+            2 => Ok(ChannelLayout::Stereo),
+            _ => Err(AudioInputError::UnsupportedChannels(channels)),
+        }
+    }
+
+    pub fn count(&self) -> ChannelCount {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+        }
+    }
+}
+
+/// Counters for the ways a capture can fail to be gap-free.
+///
+/// These are updated from the audio thread, and copied into the model's properties whenever a gap is reported
+/// (and every so often, in case a report was dropped because the event channel was full).
+#[derive(Default)]
+pub struct StreamStatistics {
+    /// Number of times a subscriber's ring buffer couldn't fit all of a push
+    pub overruns: AtomicU64,
+    /// Number of gaps in the capture timeline reported by the source
+    pub underruns: AtomicU64,
+    /// Total number of samples discarded due to overruns
+    pub dropped_samples: AtomicU64,
+}
+
+impl StreamStatistics {
+    pub fn reset(&self) {
+        self.overruns.store(0, Ordering::Relaxed);
+        self.underruns.store(0, Ordering::Relaxed);
+        self.dropped_samples.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Messages sent from the audio thread (and the threads writing files) to the main loop
+pub enum StreamEvent {
+    Error(String),
+    Discontinuity,
+    /// A recording or saved history has been written to this file
+    Saved(PathBuf),
+}
+
+pub type TapId = u64;
+
+/// Receives a source's samples as they arrive, before they're expanded for the visualizers.
+///
+/// Taps are called on the audio thread, so they should hand any slow work off to another thread.
+pub trait SampleTap: Send {
+    fn write(&mut self, data: &[f32], layout: ChannelLayout);
+}
+
+/// The taps currently attached to the input, shared by all of its [SampleProducer]s.
+#[derive(Default)]
+pub struct SampleTaps {
+    taps: Mutex<Vec<(TapId, Box<dyn SampleTap>)>>,
+    next_id: AtomicU64,
+}
+
+impl SampleTaps {
+    pub fn add(&self, tap: Box<dyn SampleTap>) -> TapId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.taps.lock().unwrap().push((id, tap));
+        id
+    }
+
+    pub fn remove(&self, id: TapId) {
+        self.taps.lock().unwrap().retain(|(tap_id, _)| *tap_id != id);
+    }
+
+    /// Writes to every tap, unless the producer has been retired (which is checked under the same lock).
+    fn write(&self, data: &[f32], layout: ChannelLayout, retired: &AtomicBool) -> bool {
+        let mut taps = self.taps.lock().unwrap();
+        if retired.load(Ordering::Relaxed) { return false; }
+        for (_, tap) in taps.iter_mut() {
+            tap.write(data, layout);
+        }
+        true
+    }
+}
+
+// Samples are pushed to the subscribers in chunks this size, copied into a buffer on the stack
+const PUSH_CHUNK: usize = 256;
+
+/// The ring buffer of a visualizer listening to the input.
+pub struct Subscriber {
+    buffer: HeapProd<StereoMagnitude>,
+    /// Set while pushing, if some of the samples didn't fit
+    overran: bool,
+}
+
+impl From<HeapProd<StereoMagnitude>> for Subscriber {
+    fn from(buffer: HeapProd<StereoMagnitude>) -> Self {
+        Self { buffer, overran: false }
+    }
+}
+
+/// The ring buffers of every visualizer listening to the input.
+pub type Subscribers = Arc<Mutex<Vec<Subscriber>>>;
+
+/// A handle which sources use to feed samples to the visualizers.
+///
+/// It can be cloned and sent to whichever thread produces the audio.
+#[derive(Clone)]
+pub struct SampleProducer {
+    subscribers: Subscribers,
+    statistics: Arc<StreamStatistics>,
+    taps: Arc<SampleTaps>,
+    events: async_channel::Sender<StreamEvent>,
+    /// Set once the source is stopped, after which nothing more is pushed
+    retired: Arc<AtomicBool>,
+}
+
+impl SampleProducer {
+    pub fn new(
+        subscribers: Subscribers,
+        statistics: Arc<StreamStatistics>,
+        taps: Arc<SampleTaps>,
+        events: async_channel::Sender<StreamEvent>,
+    ) -> Self {
+        Self { subscribers, statistics, taps, events, retired: Default::default() }
+    }
+
+    /// Stops this producer and its clones from pushing anything more.
+    ///
+    /// Some sources read on threads which can't be interrupted (and only notice they were stopped after their
+    /// next read), so this makes sure that nothing they push can end up mixed in with the next source.
+    /// Both locks are taken, so any push which is already under way has finished by the time this returns.
+    pub fn retire(&self) {
+        let _taps = self.taps.taps.lock().unwrap();
+        let _subscribers = self.subscribers.lock().unwrap();
+        self.retired.store(true, Ordering::Relaxed);
+    }
+
+    /// Pushes stereo samples to every subscriber, returning the number which fit in the emptiest buffer.
+    ///
+    /// Samples that don't fit in a subscriber's buffer are discarded and counted as an overrun for it,
+    /// without holding back the others.
+    pub fn push(&self, samples: impl IntoIterator<Item=StereoMagnitude>) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if self.retired.load(Ordering::Relaxed) { return 0; }
+        // Visualizers which have been destroyed don't need any more samples
+        subscribers.retain(|subscriber| subscriber.buffer.read_is_held());
+        subscribers.iter_mut().for_each(|subscriber| subscriber.overran = false);
+
+        // This runs on the audio thread, so the samples are staged on the stack rather than collected
+        let mut samples = samples.into_iter();
+        let mut scratch = [StereoMagnitude::default(); PUSH_CHUNK];
+        let (mut pushed, mut dropped) = (0, 0);
+        loop {
+            let len = scratch.iter_mut().zip(samples.by_ref()).map(|(slot, sample)| *slot = sample).count();
+            if len == 0 {
+                break;
+            }
+            let chunk = &scratch[..len];
+            let mut most = if subscribers.is_empty() { len } else { 0 };
+            for subscriber in subscribers.iter_mut() {
+                let fit = subscriber.buffer.push_slice(chunk);
+                if fit < len {
+                    subscriber.overran = true;
+                    dropped += len - fit;
+                }
+                most = most.max(fit);
+            }
+            pushed += most;
+        }
+        let overruns = subscribers.iter().filter(|subscriber| subscriber.overran).count();
+        drop(subscribers);
+
+        if overruns > 0 {
+            self.statistics.overruns.fetch_add(overruns as u64, Ordering::Relaxed);
+            self.statistics.dropped_samples.fetch_add(dropped as u64, Ordering::Relaxed);
+            self.events.try_send(StreamEvent::Discontinuity).ok();
+        }
+        pushed
+    }
+
+    /// Pushes interleaved samples, expanding mono input to stereo.
+    ///
+    /// The taps see every sample, even those which don't fit in the buffer.
+    pub fn push_interleaved(&self, data: &[f32], layout: ChannelLayout) -> usize {
+        if !self.taps.write(data, layout, &self.retired) { return 0; }
+        match layout {
+            ChannelLayout::Mono => self.push(data.iter().map(|s| (*s, *s))),
+            ChannelLayout::Stereo => self.push(data.iter().tuples().map(|(l, r)| (*l, *r))),
+        }
+    }
+
+    /// Number of samples which can currently be pushed without an overrun in the emptiest buffer.
+    ///
+    /// A subscriber which has stopped reading (such as a hidden visualizer) doesn't hold back the rest.
+    pub fn vacant_len(&self) -> usize {
+        self.subscribers.lock().unwrap().iter()
+            .map(|subscriber| subscriber.buffer.vacant_len())
+            .max()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Records a gap in the source's timeline, such as a lost buffer or packet.
+    pub fn report_gap(&self) {
+        self.statistics.underruns.fetch_add(1, Ordering::Relaxed);
+        self.events.try_send(StreamEvent::Discontinuity).ok();
+    }
+
+    /// Forwards an error to the main loop, where it's emitted as a `stream-error` signal.
+    pub fn report_error(&self, message: String) {
+        self.events.try_send(StreamEvent::Error(message)).ok();
+    }
+}
+
+/// Anything which can produce audio for the visualizers.
+///
+/// Sources are started with a [SampleProducer], and are expected to push samples to it
+/// (typically from another thread) until they're stopped.
+pub trait AudioSource {
+    fn name(&self) -> String;
+    fn sample_rate(&self) -> u32;
+    fn channels(&self) -> ChannelLayout;
+    fn start(&mut self, producer: SampleProducer) -> Result<(), AudioInputError>;
+    fn stop(&mut self);
+}
@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{JoinHandle, sleep};
+use std::time::Duration;
+
+use crate::sources::{AudioInputError, AudioSource, ChannelLayout, SampleProducer};
+use crate::sources::pipe_source::{SampleFormat, read_pcm};
+
+// Sockets wake up this often to check whether the source was stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// Packets are held back this long, so that ones arriving out of order can be put back in place
+const JITTER_PACKETS: usize = 8;
+// Packets this far behind are treated as the start of a new stream
+const RESYNC_PACKETS: u64 = 1024;
+// Lost packets are replaced with silence, up to a limit so that a long outage doesn't flood the buffer
+const MAX_CONCEALED_SECONDS: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkProtocol {
+    /// RTP packets with uncompressed payloads, like L16 or L24 (which are big-endian)
+    Rtp,
+    /// A plain stream of raw PCM over a TCP connection
+    Tcp,
+}
+
+/// Receives audio sent over the network by another machine.
+pub struct NetworkSource {
+    protocol: NetworkProtocol,
+    address: SocketAddr,
+    format: SampleFormat,
+    sample_rate: u32,
+    channels: ChannelLayout,
+    stop_request: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NetworkSource {
+    pub fn new(
+        protocol: NetworkProtocol,
+        address: SocketAddr,
+        format: SampleFormat,
+        sample_rate: u32,
+        channels: ChannelLayout,
+    ) -> Self {
+        Self {
+            protocol,
+            address,
+            format,
+            sample_rate,
+            channels,
+            stop_request: Default::default(),
+            thread: None,
+        }
+    }
+}
+
+impl AudioSource for NetworkSource {
+    fn name(&self) -> String {
+        match self.protocol {
+            NetworkProtocol::Rtp => format!("udp://{}", self.address),
+            NetworkProtocol::Tcp => format!("tcp://{}", self.address),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    fn channels(&self) -> ChannelLayout { self.channels }
+
+    fn start(&mut self, producer: SampleProducer) -> Result<(), AudioInputError> {
+        let stop_request = Arc::clone(&self.stop_request);
+        stop_request.store(false, Ordering::Relaxed);
+        let (format, channels, sample_rate) = (self.format, self.channels, self.sample_rate);
+
+        // Binding happens up-front, so that a port which is already in use is reported immediately
+        self.thread = Some(match self.protocol {
+            NetworkProtocol::Rtp => {
+                let socket = UdpSocket::bind(self.address)?;
+                socket.set_read_timeout(Some(POLL_INTERVAL))?;
+                std::thread::spawn(move || {
+                    if let Err(err) = receive_rtp(socket, format, channels, sample_rate, &producer, &stop_request) {
+                        producer.report_error(err.to_string());
+                    }
+                })
+            }
+            NetworkProtocol::Tcp => {
+                let listener = TcpListener::bind(self.address)?;
+                listener.set_nonblocking(true)?;
+                std::thread::spawn(move || {
+                    if let Err(err) = receive_tcp(listener, format, channels, &producer, &stop_request) {
+                        producer.report_error(err.to_string());
+                    }
+                })
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop_request.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for NetworkSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Accepts one sender at a time, and waits for another whenever a connection ends.
+fn receive_tcp(
+    listener: TcpListener,
+    format: SampleFormat,
+    channels: ChannelLayout,
+    producer: &SampleProducer,
+    stop_request: &AtomicBool,
+) -> Result<(), AudioInputError> {
+    while !stop_request.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // A sender which resets its connection (or anything else going wrong with it) only ends that connection
+        let connection = stream.set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(POLL_INTERVAL)))
+            .map_err(AudioInputError::from)
+            .and_then(|_| read_pcm(stream, format, channels, producer, stop_request));
+        if let Err(err) = connection {
+            eprintln!("A network sender's connection failed: {}", err);
+        }
+        // Whatever the next sender sends won't follow on from what this one sent
+        if !stop_request.load(Ordering::Relaxed) {
+            producer.report_gap();
+        }
+    }
+    Ok(())
+}
+
+fn receive_rtp(
+    socket: UdpSocket,
+    format: SampleFormat,
+    channels: ChannelLayout,
+    sample_rate: u32,
+    producer: &SampleProducer,
+    stop_request: &AtomicBool,
+) -> Result<(), AudioInputError> {
+    let frame_bytes = format.bytes() * channels.count() as usize;
+    let max_concealed = (MAX_CONCEALED_SECONDS * sample_rate as f32) as usize * channels.count() as usize;
+    let mut jitter_buffer = JitterBuffer::default();
+    let mut packet = vec![0u8; 65536];
+    while !stop_request.load(Ordering::Relaxed) {
+        let length = match socket.recv(&mut packet) {
+            Ok(length) => length,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) =>
+                continue,
+            Err(err) => return Err(err.into()),
+        };
+        // Anything which isn't RTP is ignored, rather than being played as noise
+        let Some((sequence, payload)) = parse_rtp(&packet[..length]) else { continue };
+        let payload = &payload[..payload.len() - payload.len() % frame_bytes];
+        let samples = payload.chunks_exact(format.bytes()).map(|b| format.decode(b)).collect();
+        jitter_buffer.insert(sequence, samples);
+
+        while let Some(released) = jitter_buffer.pop() {
+            match released {
+                Released::Packet(samples) => { producer.push_interleaved(&samples, channels); }
+                Released::Lost { packets, packet_len } => {
+                    producer.report_gap();
+                    let silence = vec![0.0; (packets as usize * packet_len).min(max_concealed)];
+                    producer.push_interleaved(&silence, channels);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the sequence number and payload of an RTP packet.
+fn parse_rtp(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 { return None; }
+    let has_padding = packet[0] & 0x20 != 0;
+    let has_extension = packet[0] & 0x10 != 0;
+    let csrc_count = (packet[0] & 0x0F) as usize;
+    let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+
+    let mut start = 12 + 4 * csrc_count;
+    if has_extension {
+        let header = packet.get(start..start + 4)?;
+        start += 4 + 4 * u16::from_be_bytes([header[2], header[3]]) as usize;
+    }
+    let end = if has_padding {
+        packet.len().checked_sub(*packet.last()? as usize)?
+    } else {
+        packet.len()
+    };
+    Some((sequence, packet.get(start..end)?))
+}
+
+#[derive(Debug, PartialEq)]
+enum Released {
+    Packet(Vec<f32>),
+    Lost { packets: u64, packet_len: usize },
+}
+
+/// Puts packets back in order, and detects the ones which never arrive.
+#[derive(Default)]
+struct JitterBuffer {
+    packets: BTreeMap<u64, Vec<f32>>,
+    /// Extended sequence number of the next packet to release
+    next: Option<u64>,
+    /// Extended sequence number of the newest packet so far, used to unwrap 16-bit sequence numbers
+    newest: Option<u64>,
+    last_packet_len: usize,
+}
+
+impl JitterBuffer {
+    fn insert(&mut self, sequence: u16, samples: Vec<f32>) {
+        // Extend the sequence number to whichever wrap of the 16-bit counter is closest to the newest packet
+        let newest = self.newest.unwrap_or(1 << 32 | sequence as u64);
+        let candidate = (newest & !0xFFFF) | sequence as u64;
+        let extended = [candidate - (1 << 16), candidate, candidate + (1 << 16)].into_iter()
+            .min_by_key(|c| c.abs_diff(newest))
+            .unwrap();
+        self.newest = Some(newest.max(extended));
+
+        // A packet far behind the stream usually means the sender restarted, so follow it from here
+        if self.next.is_some_and(|next| next.saturating_sub(extended) > RESYNC_PACKETS) {
+            self.packets.clear();
+            self.next = None;
+            self.newest = Some(extended);
+        }
+        let next = *self.next.get_or_insert(extended);
+
+        // Packets which arrive after their turn has passed are dropped
+        if extended >= next {
+            self.packets.insert(extended, samples);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Released> {
+        let next = self.next?;
+        let (&first, _) = self.packets.first_key_value()?;
+        if first == next {
+            let samples = self.packets.remove(&first).unwrap();
+            self.last_packet_len = samples.len();
+            self.next = Some(next + 1);
+            Some(Released::Packet(samples))
+        } else if self.packets.len() > JITTER_PACKETS {
+            // The missing packets have been waited for long enough, so give up on them
+            self.next = Some(first);
+            Some(Released::Lost { packets: first - next, packet_len: self.last_packet_len })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An RTP packet with a minimal header (version 2, dynamic payload type 96) around a payload.
+    fn rtp(sequence: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, 96];
+        packet.extend(sequence.to_be_bytes());
+        packet.extend([0; 8]);
+        packet.extend(payload);
+        packet
+    }
+
+    /// Inserts a packet holding just its own sequence number, and returns everything it lets out.
+    fn insert(buffer: &mut JitterBuffer, sequence: u16) -> Vec<Released> {
+        buffer.insert(sequence, vec![sequence as f32]);
+        std::iter::from_fn(|| buffer.pop()).collect()
+    }
+
+    fn packet(sequence: u16) -> Released {
+        Released::Packet(vec![sequence as f32])
+    }
+
+    #[test]
+    fn parses_l16_and_l24_payloads() {
+        let packet = rtp(7, &[0x40, 0x00, 0xC0, 0x00]);
+        let (sequence, payload) = parse_rtp(&packet).unwrap();
+        assert_eq!(sequence, 7);
+        let samples: Vec<f32> = payload.chunks_exact(2).map(|b| SampleFormat::S16be.decode(b)).collect();
+        assert_eq!(samples, [0.5, -0.5]);
+
+        let packet = rtp(65535, &[0x40, 0x00, 0x00, 0x80, 0x00, 0x00]);
+        let (sequence, payload) = parse_rtp(&packet).unwrap();
+        assert_eq!(sequence, 65535);
+        let samples: Vec<f32> = payload.chunks_exact(3).map(|b| SampleFormat::S24be.decode(b)).collect();
+        assert_eq!(samples, [0.5, -1.0]);
+    }
+
+    #[test]
+    fn skips_csrcs_extensions_and_padding() {
+        // Two CSRCs, a one-word extension, and two bytes of padding
+        let mut packet = vec![0x80 | 0x20 | 0x10 | 2, 96, 0, 1];
+        packet.extend([0; 8]);
+        packet.extend([0; 8]);
+        packet.extend([0xBE, 0xDE, 0, 1, 0, 0, 0, 0]);
+        packet.extend([1, 2, 3, 4]);
+        packet.extend([0, 2]);
+        assert_eq!(parse_rtp(&packet), Some((1, &[1, 2, 3, 4][..])));
+    }
+
+    #[test]
+    fn rejects_short_and_malformed_packets() {
+        assert_eq!(parse_rtp(&[0x80, 96, 0, 1]), None);
+        // Not version 2
+        let mut packet = rtp(1, &[0, 0]);
+        packet[0] = 0x40;
+        assert_eq!(parse_rtp(&packet), None);
+        // More CSRCs than there's room for
+        let mut packet = rtp(1, &[0, 0]);
+        packet[0] |= 0x0F;
+        assert_eq!(parse_rtp(&packet), None);
+        // An extension header which is cut off
+        let mut packet = rtp(1, &[0, 0]);
+        packet[0] |= 0x10;
+        assert_eq!(parse_rtp(&packet), None);
+        // More padding than packet
+        let mut packet = rtp(1, &[0, 0, 0, 0]);
+        packet[0] |= 0x20;
+        *packet.last_mut().unwrap() = 200;
+        assert_eq!(parse_rtp(&packet), None);
+    }
+
+    #[test]
+    fn follows_sequence_numbers_through_wraparound() {
+        let mut buffer = JitterBuffer::default();
+        let released: Vec<_> = [65533, 65534, 65535, 0, 1].into_iter()
+            .flat_map(|sequence| insert(&mut buffer, sequence))
+            .collect();
+        assert_eq!(released, [65533, 65534, 65535, 0, 1].map(packet));
+    }
+
+    #[test]
+    fn reorders_packets_across_wraparound() {
+        let mut buffer = JitterBuffer::default();
+        assert_eq!(insert(&mut buffer, 65534), [packet(65534)]);
+        assert_eq!(insert(&mut buffer, 0), []);
+        assert_eq!(insert(&mut buffer, 65535), [packet(65535), packet(0)]);
+    }
+
+    #[test]
+    fn drops_duplicates_and_late_packets() {
+        let mut buffer = JitterBuffer::default();
+        assert_eq!(insert(&mut buffer, 10), [packet(10)]);
+        assert_eq!(insert(&mut buffer, 10), []);
+        assert_eq!(insert(&mut buffer, 12), []);
+        assert_eq!(insert(&mut buffer, 12), []);
+        assert_eq!(insert(&mut buffer, 11), [packet(11), packet(12)]);
+        assert_eq!(insert(&mut buffer, 9), []);
+    }
+
+    #[test]
+    fn conceals_packets_which_never_arrive() {
+        let mut buffer = JitterBuffer::default();
+        assert_eq!(insert(&mut buffer, 0), [packet(0)]);
+        // Packet 1 is lost, and is given up on once the buffer holds more than it waits for
+        for sequence in 2..2 + JITTER_PACKETS as u16 {
+            assert_eq!(insert(&mut buffer, sequence), []);
+        }
+        let released = insert(&mut buffer, 2 + JITTER_PACKETS as u16);
+        assert_eq!(released[0], Released::Lost { packets: 1, packet_len: 1 });
+        assert_eq!(released[1..], (2..=2 + JITTER_PACKETS as u16).map(packet).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn resyncs_when_the_sender_restarts() {
+        let mut buffer = JitterBuffer::default();
+        assert_eq!(insert(&mut buffer, 30000), [packet(30000)]);
+        assert_eq!(insert(&mut buffer, 30001), [packet(30001)]);
+        // Far behind the stream, which is taken as a new one rather than a very late packet
+        assert_eq!(insert(&mut buffer, 100), [packet(100)]);
+        assert_eq!(insert(&mut buffer, 101), [packet(101)]);
+    }
+}
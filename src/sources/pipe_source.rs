@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::sources::{AudioInputError, AudioSource, ChannelLayout, SampleProducer};
+
+// Reads are split into chunks this size, so that samples reach the visualizers promptly
+const CHUNK_FRAMES: usize = 512;
+
+/// Encodings of raw PCM samples, named the same way as in `arecord` and `ffmpeg`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SampleFormat {
+    U8,
+    S16le,
+    S16be,
+    /// Packed into three bytes
+    S24le,
+    /// Packed into three bytes
+    S24be,
+    S32le,
+    S32be,
+    F32le,
+    F32be,
+}
+
+impl SampleFormat {
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16le | SampleFormat::S16be => 2,
+            SampleFormat::S24le | SampleFormat::S24be => 3,
+            SampleFormat::S32le | SampleFormat::S32be | SampleFormat::F32le | SampleFormat::F32be => 4,
+        }
+    }
+
+    /// Converts one encoded sample to a float in the range -1..1
+    pub fn decode(&self, b: &[u8]) -> f32 {
+        match self {
+            SampleFormat::U8 => (b[0] as f32 - 128.0) / 128.0,
+            SampleFormat::S16le => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            SampleFormat::S16be => i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.0,
+            // 24-bit samples are shifted into the top of an i32, so the sign is extended
+            SampleFormat::S24le => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+            SampleFormat::S24be => (i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8) as f32 / 8388608.0,
+            SampleFormat::S32le => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            SampleFormat::S32be => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            SampleFormat::F32le => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            SampleFormat::F32be => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+}
+
+/// Where a [PipeSource] reads from.
+#[derive(Debug, Clone)]
+pub enum PipeInput {
+    Stdin,
+    /// A named pipe (or any other file), which is opened once the source starts
+    Fifo(PathBuf),
+}
+
+/// Reads interleaved raw PCM, for visualizing audio produced by other programs.
+pub struct PipeSource {
+    input: PipeInput,
+    format: SampleFormat,
+    sample_rate: u32,
+    channels: ChannelLayout,
+    stop_request: Arc<AtomicBool>,
+}
+
+impl PipeSource {
+    pub fn new(input: PipeInput, format: SampleFormat, sample_rate: u32, channels: ChannelLayout) -> Self {
+        Self {
+            input,
+            format,
+            sample_rate,
+            channels,
+            stop_request: Default::default(),
+        }
+    }
+}
+
+impl AudioSource for PipeSource {
+    fn name(&self) -> String {
+        match &self.input {
+            PipeInput::Stdin => "Standard Input".into(),
+            PipeInput::Fifo(path) => path.display().to_string(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    fn channels(&self) -> ChannelLayout { self.channels }
+
+    fn start(&mut self, producer: SampleProducer) -> Result<(), AudioInputError> {
+        // A fresh flag, so that a reader left over from a previous start can't be revived
+        self.stop_request = Default::default();
+        let stop_request = Arc::clone(&self.stop_request);
+        let (input, format, channels) = (self.input.clone(), self.format, self.channels);
+        std::thread::spawn(move || {
+            // Opening a FIFO blocks until something starts writing to it, so it's done here
+            let result = match input {
+                PipeInput::Stdin => read_pcm(io::stdin().lock(), format, channels, &producer, &stop_request),
+                PipeInput::Fifo(path) => File::open(path)
+                    .map_err(AudioInputError::from)
+                    .and_then(|file| read_pcm(file, format, channels, &producer, &stop_request)),
+            };
+            if !stop_request.load(Ordering::Relaxed) {
+                match result {
+                    Ok(()) => producer.report_error("The input stream ended".into()),
+                    Err(err) => producer.report_error(err.to_string()),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // Reads can block indefinitely, so the thread isn't joined; it exits after its next read
+        self.stop_request.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for PipeSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Decodes raw PCM from a reader until it ends, or the source is stopped.
+pub fn read_pcm(
+    mut reader: impl Read,
+    format: SampleFormat,
+    channels: ChannelLayout,
+    producer: &SampleProducer,
+    stop_request: &AtomicBool,
+) -> Result<(), AudioInputError> {
+    let frame_bytes = format.bytes() * channels.count() as usize;
+    let mut buffer = vec![0u8; CHUNK_FRAMES * frame_bytes];
+    let mut filled = 0;
+    let mut samples = Vec::with_capacity(CHUNK_FRAMES * channels.count() as usize);
+    while !stop_request.load(Ordering::Relaxed) {
+        let read = match reader.read(&mut buffer[filled..]) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            // Timeouts give sockets a chance to check whether the source was stopped
+            Err(err) if matches!(err.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                continue,
+            Err(err) => return Err(err.into()),
+        };
+        filled += read;
+
+        // Only whole frames are pushed; a partial frame is kept until the rest of it arrives
+        let usable = filled - filled % frame_bytes;
+        samples.clear();
+        samples.extend(buffer[..usable].chunks_exact(format.bytes()).map(|b| format.decode(b)));
+        // A reader which outlived its source is stopped by the producer, which drops whatever it pushes
+        producer.push_interleaved(&samples, channels);
+        buffer.copy_within(usable..filled, 0);
+        filled -= usable;
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use gtk::glib;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use ringbuf::{HeapCons, HeapProd, HeapRb, traits::{Consumer, Observer, Producer, Split}};
+
+use crate::output_paths::timestamped_path;
+use crate::sources::{AudioInputError, ChannelLayout, SampleTap, SampleTaps, StreamEvent, TapId, flac_writer::FlacWriter};
+
+// The disk can stall for this long before the recording has to skip some of the input
+const BUFFER_SECONDS: f64 = 2.0;
+// How often the writer checks for new samples, and how many it takes at once
+const WRITE_INTERVAL: Duration = Duration::from_millis(20);
+const WRITE_CHUNK: usize = 4096;
+
+/// The kinds of file which the input can be saved as.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "RecordingFormat")]
+pub enum RecordingFormat {
+    /// Uncompressed floats, exactly as the visualizers saw them
+    #[default]
+    Wav = 0,
+    /// Lossless compression of the samples rounded to 24 bits, at around half the size
+    Flac = 1,
+}
+
+impl RecordingFormat {
+    pub const ALL: [RecordingFormat; 2] = [Self::Wav, Self::Flac];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Wav => "WAV",
+            Self::Flac => "FLAC",
+        }
+    }
+
+    pub fn position(&self) -> u32 {
+        Self::ALL.iter().position(|format| format == self).unwrap() as u32
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+        }
+    }
+}
+
+/// Chooses a new file in the user's music directory, named after the current time.
+pub fn default_recording_path(format: RecordingFormat) -> PathBuf {
+    timestamped_path(glib::UserDirectory::Music, format.extension())
+}
+
+/// Samples are stored as 32-bit floats, so recordings are exact copies of what the visualizers saw.
+pub fn wav_spec(sample_rate: u32, channels: ChannelLayout) -> WavSpec {
+    WavSpec {
+        channels: channels.count(),
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    }
+}
+
+/// A recording file being written, in either format.
+pub enum RecordingWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Flac(FlacWriter),
+}
+
+impl RecordingWriter {
+    pub fn create(
+        path: &Path,
+        format: RecordingFormat,
+        sample_rate: u32,
+        channels: ChannelLayout,
+    ) -> Result<Self, AudioInputError> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        Ok(match format {
+            RecordingFormat::Wav => Self::Wav(WavWriter::create(path, wav_spec(sample_rate, channels))?),
+            RecordingFormat::Flac => Self::Flac(FlacWriter::create(path, sample_rate, channels)?),
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), AudioInputError> {
+        match self {
+            Self::Wav(writer) => {
+                for sample in samples {
+                    writer.write_sample(*sample)?;
+                }
+            }
+            Self::Flac(writer) => writer.write_samples(samples)?,
+        }
+        Ok(())
+    }
+
+    pub fn finalize(self) -> Result<(), AudioInputError> {
+        match self {
+            Self::Wav(writer) => writer.finalize()?,
+            Self::Flac(writer) => writer.finalize()?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes the raw input to a file, with the source's own sample rate and channel count.
+///
+/// Once the file is finished, its writer reports `Saved` (or `Error`, if anything went wrong) to the events.
+pub struct Recorder {
+    tap: TapId,
+}
+
+impl Recorder {
+    pub fn start(
+        path: PathBuf,
+        format: RecordingFormat,
+        sample_rate: u32,
+        channels: ChannelLayout,
+        taps: &SampleTaps,
+        events: async_channel::Sender<StreamEvent>,
+    ) -> Result<Self, AudioInputError> {
+        let writer = RecordingWriter::create(&path, format, sample_rate, channels)?;
+
+        // The disk is written from another thread, so the audio thread never waits on it (or allocates for it)
+        let capacity = (BUFFER_SECONDS * sample_rate as f64) as usize * channels.count() as usize;
+        let (buffer, consumer) = HeapRb::new(capacity.max(WRITE_CHUNK)).split();
+        let skipped = Arc::new(AtomicBool::new(false));
+        std::thread::spawn({
+            let skipped = Arc::clone(&skipped);
+            move || {
+                let event = match write_samples(writer, consumer, &skipped, &events) {
+                    Ok(()) => StreamEvent::Saved(path),
+                    Err(err) => StreamEvent::Error(format!("Failed to save the recording: {}", err)),
+                };
+                events.send_blocking(event).ok();
+            }
+        });
+        let tap = taps.add(Box::new(RecordingTap { buffer, channels, skipped }));
+
+        Ok(Self { tap })
+    }
+
+    /// Detaches from the input, leaving the writer to finish the file in the background.
+    pub fn stop(self, taps: &SampleTaps) {
+        // Removing the tap drops its end of the buffer, which lets the writer finalize the file
+        taps.remove(self.tap);
+    }
+}
+
+struct RecordingTap {
+    buffer: HeapProd<f32>,
+    channels: ChannelLayout,
+    /// Set when the buffer was full, so the writer can report it from its own thread
+    skipped: Arc<AtomicBool>,
+}
+
+impl SampleTap for RecordingTap {
+    fn write(&mut self, data: &[f32], layout: ChannelLayout) {
+        // The header can't change partway through the file
+        if layout == self.channels && self.buffer.push_slice(data) < data.len() {
+            self.skipped.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writes whatever arrives in the buffer, until the tap is removed and the rest has been written.
+fn write_samples(
+    mut writer: RecordingWriter,
+    mut buffer: HeapCons<f32>,
+    skipped: &AtomicBool,
+    events: &async_channel::Sender<StreamEvent>,
+) -> Result<(), AudioInputError> {
+    let mut chunk = vec![0.0; WRITE_CHUNK];
+    loop {
+        // Checked before reading, so that nothing written just before the tap was removed is missed
+        let finished = !buffer.write_is_held();
+        let count = buffer.pop_slice(&mut chunk);
+        if skipped.swap(false, Ordering::Relaxed) {
+            events.send_blocking(StreamEvent::Error("The recording fell behind, and skipped some of the input".into())).ok();
+        }
+        if count > 0 {
+            writer.write_samples(&chunk[..count])?;
+        } else if finished {
+            break;
+        } else {
+            std::thread::sleep(WRITE_INTERVAL);
+        }
+    }
+    writer.finalize()
+}
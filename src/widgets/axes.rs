@@ -0,0 +1,224 @@
+use gtk::{gdk::RGBA, graphene::{Point, Rect}, prelude::*};
+use plotters::coord::ranged1d::Ranged;
+
+use crate::fourier::Frequency;
+use crate::frequency_scale::FrequencyScale;
+use crate::log_scaling::{IntoReversibleLogRange, LogCoordf64};
+use crate::widgets::spectrogram_orientation::SpectrogramOrientation;
+
+const TICK_LENGTH: f32 = 6.0;
+const LABEL_MARGIN: f32 = 3.0;
+// Labels closer together than this would overlap, on axes which run up and across the widget respectively
+const MIN_VERTICAL_LABEL_SPACING: f32 = 24.0;
+const MIN_HORIZONTAL_LABEL_SPACING: f32 = 80.0;
+// Wide enough for the ticks and labels along the frequency axis
+const FREQUENCY_STRIP_WIDTH: f32 = 56.0;
+const TIME_STEPS: [f32; 10] = [0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0];
+const LEVEL_STEPS: [f32; 7] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+// These are (rounded) powers of two from 1 kHz, so they're evenly spaced on the octave scale
+const ISO_OCTAVE_CENTRES: [f64; 11] = [16.0, 31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+
+/// A labelled point along an axis, measured in pixels from the start of the axis.
+pub struct Tick {
+    pub position: f32,
+    pub label: String,
+}
+
+/// The log scale used by the spectrograms, which places frequencies the same way their shader does.
+fn log_frequency_scale(min_frequency: f32, max_frequency: f32) -> LogCoordf64 {
+    (min_frequency as f64..max_frequency as f64).reversible_log_scale().zero_point(0.0).into()
+}
+
+fn label_spacing(vertical_axis: bool) -> f32 {
+    if vertical_axis { MIN_VERTICAL_LABEL_SPACING } else { MIN_HORIZONTAL_LABEL_SPACING }
+}
+
+/// Ticks at round frequencies (100 Hz, 200 Hz, … 1 kHz, …), measured from the low end of the frequency axis.
+pub fn frequency_ticks(
+    scale: FrequencyScale,
+    range: (Frequency, Frequency),
+    orientation: SpectrogramOrientation,
+    size: (f32, f32),
+) -> Vec<Tick> {
+    let (_, length) = orientation.axis_lengths(size);
+    let max_points = (length / label_spacing(!orientation.is_vertical())).max(1.0) as usize;
+    frequency_key_points(scale, range, max_points).into_iter()
+        .map(|frequency| Tick {
+            position: scale.map(frequency as Frequency, range) * length,
+            label: format_frequency(frequency),
+        })
+        .collect()
+}
+
+/// Round frequencies within a range which suit the scale, roughly `max_points` of them (or more, where
+/// they bunch up along the axis and the caller is expected to skip some).
+pub fn frequency_key_points(scale: FrequencyScale, range: (Frequency, Frequency), max_points: usize) -> Vec<f64> {
+    match scale {
+        FrequencyScale::Log => log_frequency_scale(range.0, range.1).key_points(max_points),
+        FrequencyScale::Linear => linear_key_points(range, max_points),
+        FrequencyScale::Octave => octave_key_points(range),
+        // The perceptual scales are roughly linear at the bottom and logarithmic at the top,
+        // so round numbers from every decade are offered
+        _ => decade_key_points(range),
+    }
+}
+
+/// Evenly spaced round frequencies, like 0, 5, 10, 15 kHz.
+fn linear_key_points((min, max): (Frequency, Frequency), max_points: usize) -> Vec<f64> {
+    let rough_step = (max - min) as f64 / max_points as f64;
+    let magnitude = 10f64.powf(rough_step.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].into_iter()
+        .map(|multiple| multiple * magnitude)
+        .find(|&step| step >= rough_step)
+        .unwrap_or(10.0 * magnitude);
+    let first = (min as f64 / step).ceil() as i64;
+    let last = (max as f64 / step).floor() as i64;
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+/// The nominal centres of the ISO octave bands within a range.
+fn octave_key_points((min, max): (Frequency, Frequency)) -> Vec<f64> {
+    ISO_OCTAVE_CENTRES.into_iter()
+        .filter(|frequency| (min as f64..=max as f64).contains(frequency))
+        .collect()
+}
+
+/// The frequencies 1, 2 and 5 times every power of ten within a range.
+fn decade_key_points((min, max): (Frequency, Frequency)) -> Vec<f64> {
+    let (min, max) = (min.max(1.0) as f64, max as f64);
+    (min.log10().floor() as i32..=max.log10().ceil() as i32)
+        .flat_map(|exponent| [1.0, 2.0, 5.0].map(|multiple| multiple * 10f64.powi(exponent)))
+        .filter(|frequency| (min..=max).contains(frequency))
+        .collect()
+}
+
+/// Ticks at round numbers of seconds ago, measured from the oldest end of a time axis which spans `seconds`
+/// and ends `newest` seconds ago.
+pub fn time_ticks(newest: f32, seconds: f32, orientation: SpectrogramOrientation, size: (f32, f32)) -> Vec<Tick> {
+    let (length, _) = orientation.axis_lengths(size);
+    let max_ticks = (length / label_spacing(orientation.is_vertical())).max(1.0);
+    let step = TIME_STEPS.into_iter()
+        .find(|step| seconds / step <= max_ticks)
+        .unwrap_or(TIME_STEPS[TIME_STEPS.len() - 1]);
+    ((newest / step).floor() as i64 + 1..)
+        .map(|i| i as f32 * step)
+        .take_while(|&time| time < newest + seconds)
+        .map(|time| Tick {
+            position: length * (1.0 - (time - newest) / seconds),
+            label: format_seconds(time),
+        })
+        .collect()
+}
+
+/// Ticks at round levels (-60 dB, -50 dB, …), measured from the bottom of a level axis of the given length.
+pub fn level_ticks(min_db: f32, max_db: f32, length: f32) -> Vec<Tick> {
+    let span = (max_db - min_db).max(f32::EPSILON);
+    let max_ticks = (length / MIN_VERTICAL_LABEL_SPACING).max(1.0);
+    let step = LEVEL_STEPS.into_iter()
+        .find(|step| span / step <= max_ticks)
+        .unwrap_or(LEVEL_STEPS[LEVEL_STEPS.len() - 1]);
+    ((min_db / step).ceil() as i64..=(max_db / step).floor() as i64)
+        .map(|i| i as f32 * step)
+        .map(|db| Tick {
+            position: length * (db - min_db) / span,
+            label: format!("{} dB", trim_decimals(db as f64)),
+        })
+        .collect()
+}
+
+pub fn format_frequency(frequency: f64) -> String {
+    if frequency >= 1000.0 {
+        format!("{} kHz", trim_decimals(frequency / 1000.0))
+    } else {
+        format!("{} Hz", trim_decimals(frequency))
+    }
+}
+
+fn format_seconds(seconds: f32) -> String {
+    format!("-{} s", trim_decimals(seconds as f64))
+}
+
+fn trim_decimals(value: f64) -> String {
+    let text = format!("{:.2}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Whether a point is over the frequency axis, where gestures adjust the range instead of reaching the pane.
+pub fn in_frequency_strip(orientation: SpectrogramOrientation, (x, y): (f32, f32), (_, height): (f32, f32)) -> bool {
+    if orientation.is_vertical() { y >= height - FREQUENCY_STRIP_WIDTH } else { x <= FREQUENCY_STRIP_WIDTH }
+}
+
+/// Draws the frequency axis up the left edge and the time axis along the bottom (or the other way around,
+/// when time runs vertically), with optional grid lines.
+pub fn draw_axes(
+    widget: &gtk::Widget,
+    snapshot: &gtk::Snapshot,
+    orientation: SpectrogramOrientation,
+    frequency_ticks: &[Tick],
+    time_ticks: &[Tick],
+    show_grid: bool,
+) {
+    let size = (widget.width() as f32, widget.height() as f32);
+    let (time_length, frequency_length) = orientation.axis_lengths(size);
+    let vertical = orientation.is_vertical();
+
+    // Ticks can bunch up (towards the top on a log scale), so labels which would overlap the last one are skipped
+    let spacing = label_spacing(!vertical);
+    let mut last_label = f32::NEG_INFINITY;
+    for tick in frequency_ticks {
+        let label = (tick.position - last_label >= spacing).then_some(tick.label.as_str());
+        if label.is_some() {
+            last_label = tick.position;
+        }
+        let (x, y) = orientation.point((0.0, tick.position / frequency_length), size);
+        if vertical {
+            draw_bottom_tick(widget, snapshot, x, label, show_grid);
+        } else {
+            draw_left_tick(widget, snapshot, y, label, show_grid);
+        }
+    }
+
+    for tick in time_ticks {
+        let (x, y) = orientation.point((tick.position / time_length, 0.0), size);
+        if vertical {
+            draw_left_tick(widget, snapshot, y, Some(&tick.label), show_grid);
+        } else {
+            draw_bottom_tick(widget, snapshot, x, Some(&tick.label), show_grid);
+        }
+    }
+}
+
+fn draw_left_tick(widget: &gtk::Widget, snapshot: &gtk::Snapshot, y: f32, label: Option<&str>, show_grid: bool) {
+    if show_grid {
+        snapshot.append_color(&RGBA::new(1.0, 1.0, 1.0, 0.2), &Rect::new(0.0, y, widget.width() as f32, 1.0));
+    }
+    snapshot.append_color(&RGBA::new(1.0, 1.0, 1.0, 0.8), &Rect::new(0.0, y, TICK_LENGTH, 1.0));
+    if let Some(label) = label {
+        draw_label(widget, snapshot, label, TICK_LENGTH + LABEL_MARGIN, y, 0.0, 0.5);
+    }
+}
+
+fn draw_bottom_tick(widget: &gtk::Widget, snapshot: &gtk::Snapshot, x: f32, label: Option<&str>, show_grid: bool) {
+    let height = widget.height() as f32;
+    if show_grid {
+        snapshot.append_color(&RGBA::new(1.0, 1.0, 1.0, 0.2), &Rect::new(x, 0.0, 1.0, height));
+    }
+    snapshot.append_color(&RGBA::new(1.0, 1.0, 1.0, 0.8), &Rect::new(x, height - TICK_LENGTH, 1.0, TICK_LENGTH));
+    if let Some(label) = label {
+        draw_label(widget, snapshot, label, x, height - TICK_LENGTH - LABEL_MARGIN, 0.5, 1.0);
+    }
+}
+
+/// Draws text with a shadow, so it stays readable over any part of the palette.
+fn draw_label(widget: &gtk::Widget, snapshot: &gtk::Snapshot, text: &str, x: f32, y: f32, align_x: f32, align_y: f32) {
+    let layout = widget.create_pango_layout(Some(text));
+    let (text_width, text_height) = layout.pixel_size();
+    let x = (x - align_x * text_width as f32).max(0.0);
+    let y = (y - align_y * text_height as f32).max(0.0);
+    for (offset, color) in [(1.0, RGBA::new(0.0, 0.0, 0.0, 0.8)), (0.0, RGBA::WHITE)] {
+        snapshot.save();
+        snapshot.translate(&Point::new(x + offset, y + offset));
+        snapshot.append_layout(&layout, &color);
+        snapshot.restore();
+    }
+}
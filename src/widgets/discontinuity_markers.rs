@@ -0,0 +1,31 @@
+use std::cell::Cell;
+
+// Markers cover this fraction of a ring (but at least a couple of rows), so they remain visible at typical window sizes
+const RING_FRACTION: usize = 256;
+const MIN_ROWS: usize = 2;
+
+/// Follows the input's running count of gaps, so that the frames around each one can be marked.
+#[derive(Debug, Default)]
+pub struct DiscontinuityMarkers {
+    count: Cell<u64>,
+    pending: Cell<bool>,
+}
+
+impl DiscontinuityMarkers {
+    pub fn count(&self) -> u64 {
+        self.count.get()
+    }
+
+    pub fn set_count(&self, count: u64) {
+        // The count is reset when the input changes, which isn't a gap in itself
+        if count > self.count.replace(count) {
+            self.pending.set(true);
+        }
+    }
+
+    /// The rows of a ring to mark if a gap was reported since the last call, counting back from the row before `next`.
+    pub fn take_rows(&self, next: usize, ring_length: usize) -> impl Iterator<Item=usize> {
+        let rows = if self.pending.take() { (ring_length / RING_FRACTION).max(MIN_ROWS).min(ring_length) } else { 0 };
+        (1..=rows).map(move |i| (next + ring_length - i) % ring_length)
+    }
+}
@@ -0,0 +1,150 @@
+use std::rc::Rc;
+
+use glium::Rect;
+use glium::backend::Context;
+use glium::texture::{MipmapsOption, Texture2d, UncompressedFloatFormat};
+use ringbuf::{HeapRb, traits::Split};
+
+use crate::colorscheme::ColorScheme;
+use crate::fourier::{Frequency, Period, StereoMagnitude, fft::{DEFAULT_WINDOW_PERIOD, FastFourierTransform}, audio_transform::{AudioStreamTransform, AudioTransform}};
+
+/// Transforms a stream of samples into frames, and keeps the most recent ones as the rows of a texture.
+///
+/// Each row spans everything up to the nyquist frequency. Rows are written around a ring,
+/// so the offset is both where the next frame goes and where the oldest one is.
+pub struct FrameRing {
+    pub fft: AudioStreamTransform<FastFourierTransform>,
+    rows: usize,
+    mipmaps: MipmapsOption,
+    texture: Option<Texture2d>,
+    offset: usize,
+}
+
+impl FrameRing {
+    /// Starts out with a placeholder input and transform, until the real ones are known.
+    pub fn new(stride: Period, rows: usize, mipmaps: MipmapsOption) -> Self {
+        let (_, dummy_sample_stream) = HeapRb::new(1).split();
+        Self {
+            fft: AudioStreamTransform::new(
+                dummy_sample_stream,
+                FastFourierTransform::new(100 as Frequency, 1 as Period),
+                stride,
+            ),
+            rows,
+            mipmaps,
+            texture: None,
+            offset: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.fft.transform.sample_rate() as u32
+    }
+
+    pub fn nyquist_frequency(&self) -> Frequency {
+        self.fft.transform.sample_rate() / 2.0
+    }
+
+    /// Replaces the transform, which changes how many frequencies each row holds.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.fft.transform = FastFourierTransform::new(
+            sample_rate as Frequency,
+            DEFAULT_WINDOW_PERIOD,
+        );
+        self.reset();
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn set_rows(&mut self, rows: usize) {
+        self.rows = rows;
+        self.reset();
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Drops the texture, so that it's rebuilt empty the next time it's prepared.
+    pub fn reset(&mut self) {
+        self.texture = None;
+        self.offset = 0;
+    }
+
+    /// Creates the texture if it's missing, returning whether it was.
+    pub fn prepare(&mut self, context: &Rc<Context>) -> bool {
+        if self.texture.is_some() {
+            return false;
+        }
+        self.texture = Some(Texture2d::empty_with_format(
+            context,
+            UncompressedFloatFormat::F16F16,
+            self.mipmaps,
+            self.fft.transform.num_output_frequencies() as u32,
+            self.rows as u32,
+        ).unwrap());
+        self.offset = 0;
+        true
+    }
+
+    /// The texture, which must have been prepared first.
+    pub fn texture(&self) -> &Texture2d {
+        self.texture.as_ref().expect("the frame ring's texture hasn't been prepared")
+    }
+
+    /// Writes frames into the ring, which must all fit before it wraps around.
+    fn write_block(&mut self, frames: Vec<Vec<StereoMagnitude>>) {
+        let texture = self.texture();
+        let height = frames.len() as u32;
+        // A new sample rate resets the ring, so the texture is always as wide as the frames
+        texture.write(Rect { left: 0, bottom: self.offset as u32, width: frames[0].len() as u32, height }, frames);
+        self.offset = (self.offset + height as usize) % self.rows;
+    }
+
+    /// Writes frames which were kept elsewhere (such as after a resize), as many as fit from the newest back.
+    pub fn restore(&mut self, frames: Vec<Vec<StereoMagnitude>>) {
+        let width = self.texture().width() as usize;
+        let frames: Vec<_> = frames.into_iter().rev().take(self.rows).filter(|frame| frame.len() == width).collect();
+        if !frames.is_empty() {
+            self.write_block(frames.into_iter().rev().collect());
+        }
+    }
+
+    /// Transforms everything waiting in the input into the ring.
+    ///
+    /// Each block of new frames is handed to `on_block` first, along with the row it starts at.
+    pub fn fill(&mut self, mut on_block: impl FnMut(&[Vec<StereoMagnitude>], usize)) {
+        loop {
+            let remaining_space = self.rows - self.offset;
+            let new_frames: Vec<_> = self.fft.process().take(remaining_space).collect();
+            if new_frames.is_empty() {
+                break;
+            }
+            on_block(&new_frames, self.offset);
+            self.write_block(new_frames);
+        }
+    }
+}
+
+/// Forgets which textures glium thinks are bound, since they may have been clobbered by a bug elsewhere
+/// (see: https://github.com/glium/glium/issues/2106).
+pub fn rebind_texture_units(context: &Rc<Context>) {
+    unsafe {
+        context.exec_with_context(|c| {
+            c.state.texture_units.iter_mut().for_each(|t| *t = Default::default());
+            epoxy::ActiveTexture(epoxy::TEXTURE0 + c.state.active_texture);
+        });
+    };
+}
+
+/// A lookup table of the palette's colors, indexed by pan across and level up.
+pub fn palette_texture(context: &Rc<Context>, palette: &ColorScheme) -> Texture2d {
+    Texture2d::with_format(
+        context,
+        palette.lookup_table(32),
+        UncompressedFloatFormat::F32F32F32F32,
+        MipmapsOption::NoMipmap,
+    ).unwrap()
+}
@@ -5,33 +5,62 @@ use itertools::Itertools;
 
 use gtk::{gdk, glib, prelude::*, subclass::prelude::*};
 
-use adw::glib::{Properties, Object, ControlFlow::Continue, property::PropertySet};
+use adw::glib::{clone, Properties, Object, ControlFlow::Continue, property::PropertySet};
 use adw::subclass::prelude::ObjectSubclassExt;
 
-use ringbuf::{HeapRb, HeapCons, traits::{Split, Observer}};
-use ringbuf_blocking::traits::Consumer;
+use ringbuf::HeapCons;
 
-use crate::fourier::{Frequency, Period, StereoMagnitude, fft::FastFourierTransform, audio_transform::AudioStreamTransform};
+use crate::fourier::StereoMagnitude;
 
 use glium::{index::PrimitiveType, program, uniform, Frame, Surface, Blend, Smooth::Nicest};
 
-use crate::colorscheme::ColorScheme;
+use crate::colorscheme::{ColorScheme, DEFAULT_MAX_DB, DEFAULT_MIN_DB};
+use crate::fourier::frame_history::FrameHistory;
+use crate::frequency_scale::{FrequencyScale, MAX_FREQUENCY, MIN_FREQUENCY};
+use crate::widgets::axes;
+use crate::widgets::discontinuity_markers::DiscontinuityMarkers;
+use crate::widgets::frame_ring::{self, FrameRing};
+use crate::widgets::level_range::LevelTracker;
+use crate::widgets::readout;
+use crate::widgets::spectrogram_orientation::SpectrogramOrientation;
 use crate::widgets::glarea_backend::GLAreaBackend;
-
-const VIEWPORT_FRAMES: usize = 2048;
-const VIEWPORT_SECONDS: f32 = 2.5f32;
-const FRAMES_PER_SECOND: f32 = VIEWPORT_FRAMES as f32 / VIEWPORT_SECONDS;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt, VisualizerImpl};
+
+pub const DEFAULT_DURATION: f32 = 2.5;
+pub const DEFAULT_FRAME_RATE: f32 = 2048.0 / DEFAULT_DURATION;
+// Each frame is a row of the fft texture (and of the history kept on the CPU), which can't grow without limit
+const MIN_FRAMES: usize = 16;
+const MAX_FRAMES: usize = 8192;
+// Relative difference between frame rates which are treated as the same when resizing
+const FRAME_RATE_TOLERANCE: f32 = 1e-4;
+// Frames are kept on the CPU for this long, so a frozen display can be scrolled back.
+// Long histories of finely divided frames are cut short to stay within the memory budget
+const SCROLLBACK_SECONDS: f32 = 60.0;
+const SCROLLBACK_BYTES: usize = 64 << 20;
+// Zooming stops at these limits, since nothing useful can be seen beyond them
+const LOWEST_FREQUENCY: f32 = 1.0;
+const MIN_SPAN_FRACTION: f32 = 0.005;
+// The frequency axis is drawn by interpolating between this many frequencies, about one for each pixel
+const FREQUENCY_POINTS: usize = 2048;
+// Each step of the scroll wheel zooms by this factor, or scrolls by this fraction of the view
+const ZOOM_STEP: f32 = 1.15;
+const TIME_SCROLL_STEP: f32 = 0.1;
 
 glib::wrapper! {
     pub struct GPUSpectrogram(ObjectSubclass<imp::GPUSpectrogram>)
-        @extends gtk::GLArea, gtk::Widget;
+        @extends gtk::GLArea, gtk::Widget,
+        @implements Visualizer;
 }
 
 impl GPUSpectrogram {
     pub fn new(sample_stream: HeapCons<StereoMagnitude>) -> GPUSpectrogram {
-        let object = Object::builder().build();
-        let imp = imp::GPUSpectrogram::from_obj(&object);
-        imp.fft.borrow_mut().input_stream = sample_stream;
+        let object: GPUSpectrogram = Object::builder().build();
+        object.attach_input(sample_stream);
+        readout::track_pointer(&object, clone!(
+            #[weak] object,
+            move |pointer| object.imp().pointer.set(pointer)
+        ));
+        add_gestures(&object);
         object.add_tick_callback(|spectrogram, _| {
             // todo: only draw if there are unprocessed samples!
             spectrogram.queue_draw();
@@ -39,6 +68,154 @@ impl GPUSpectrogram {
         });
         object
     }
+
+    pub fn frequency_range(&self) -> (f32, f32) {
+        (self.min_frequency(), self.max_frequency())
+    }
+
+    /// Shows a new range of frequencies, kept within what the input can actually contain.
+    pub fn set_frequency_range(&self, range: (f32, f32)) {
+        self.imp().set_frequency_range(range);
+    }
+
+    pub fn reset_frequency_range(&self) {
+        self.set_frequency_range((MIN_FREQUENCY, self.imp().highest_frequency()));
+    }
+
+    /// Moves a frozen view through the scrollback by a fraction of its width, with negative fractions going back in time.
+    pub fn scroll_time(&self, fraction: f32) {
+        if let Some((end, frames)) = self.imp().view.get() {
+            self.imp().set_view(end + (fraction * frames as f32) as i64, frames);
+        }
+    }
+
+    /// Zooms a frozen view in (factor < 1) or out (factor > 1) around an anchor, which goes from 0 at the left to 1 at the right.
+    pub fn zoom_time(&self, anchor: f32, factor: f32) {
+        if let Some((end, frames)) = self.imp().view.get() {
+            let anchor_frame = end as f32 - frames as f32 * (1.0 - anchor);
+            let frames = (frames as f32 * factor).max(1.0);
+            self.imp().set_view((anchor_frame + frames * (1.0 - anchor)) as i64, frames as usize);
+        }
+    }
+}
+
+/// Scales a frequency range around an anchor, which goes from 0 at the bottom to 1 at the top.
+fn zoomed(scale: FrequencyScale, (min, max): (f32, f32), anchor: f32, factor: f32) -> (f32, f32) {
+    let (min, max) = (scale.warp(min), scale.warp(max));
+    let anchor = min + (max - min) * anchor;
+    (scale.unwarp(anchor - (anchor - min) * factor), scale.unwarp(anchor + (max - anchor) * factor))
+}
+
+/// Shifts a frequency range by a fraction of its height.
+fn panned(scale: FrequencyScale, (min, max): (f32, f32), fraction: f32) -> (f32, f32) {
+    let (min, max) = (scale.warp(min), scale.warp(max));
+    let shift = (max - min) * fraction;
+    (scale.unwarp(min + shift), scale.unwarp(max + shift))
+}
+
+fn add_gestures(spectrogram: &GPUSpectrogram) {
+    // Scrolling zooms in and out around the frequency under the pointer.
+    // While frozen, scrolling sideways (or with shift) moves through time, and scrolling with ctrl zooms in time
+    let scroll = gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::BOTH_AXES);
+    scroll.connect_scroll(clone!(
+        #[weak] spectrogram,
+        #[upgrade_or] glib::Propagation::Proceed,
+        move |controller, dx, dy| {
+            let modifiers = controller.current_event_state();
+            let size = (spectrogram.width().max(1) as f32, spectrogram.height().max(1) as f32);
+            let pointer = spectrogram.imp().pointer.get()
+                .map(|pointer| spectrogram.orientation().fractions(pointer, size));
+            if spectrogram.is_paused() && modifiers.contains(gdk::ModifierType::CONTROL_MASK) {
+                let anchor = pointer.map_or(1.0, |(time, _)| time);
+                spectrogram.zoom_time(anchor, ZOOM_STEP.powf(dy as f32));
+            } else if spectrogram.is_paused() && (dx != 0.0 || modifiers.contains(gdk::ModifierType::SHIFT_MASK)) {
+                let delta = if dx != 0.0 { dx } else { dy };
+                spectrogram.scroll_time(delta as f32 * TIME_SCROLL_STEP);
+            } else if dy != 0.0 {
+                let anchor = pointer.map_or(0.5, |(_, frequency)| frequency);
+                spectrogram.set_frequency_range(zoomed(spectrogram.frequency_scale(), spectrogram.frequency_range(), anchor, ZOOM_STEP.powf(dy as f32)));
+            }
+            glib::Propagation::Stop
+        }
+    ));
+    spectrogram.add_controller(scroll);
+
+    // Dragging the frequency axis moves the range, following the pointer (and moves through time too, while frozen).
+    // Drags and clicks anywhere else are left alone, so they still select the pane
+    let start_range = Rc::new(Cell::new(spectrogram.frequency_range()));
+    let start_view = Rc::new(Cell::new(None));
+    let drag = gtk::GestureDrag::new();
+    drag.connect_drag_begin(clone!(
+        #[weak] spectrogram,
+        #[strong] start_range,
+        #[strong] start_view,
+        move |gesture, x, y| {
+            let size = (spectrogram.width() as f32, spectrogram.height() as f32);
+            if !axes::in_frequency_strip(spectrogram.orientation(), (x as f32, y as f32), size) {
+                gesture.set_state(gtk::EventSequenceState::Denied);
+                return;
+            }
+            start_range.set(spectrogram.frequency_range());
+            start_view.set(spectrogram.imp().view.get());
+        }
+    ));
+    drag.connect_drag_update(clone!(
+        #[weak] spectrogram,
+        #[strong] start_range,
+        #[strong] start_view,
+        move |_, dx, dy| {
+            // The content follows the pointer, so the view moves the opposite way
+            let size = (spectrogram.width().max(1) as f32, spectrogram.height().max(1) as f32);
+            let orientation = spectrogram.orientation();
+            let (time, frequency) = orientation.fractions((dx as f32, dy as f32), size);
+            let (start_time, start_frequency) = orientation.fractions((0.0, 0.0), size);
+            spectrogram.set_frequency_range(panned(spectrogram.frequency_scale(), start_range.get(), start_frequency - frequency));
+            if let Some((end, frames)) = start_view.get() {
+                spectrogram.imp().set_view(end - ((time - start_time) * frames as f32) as i64, frames);
+            }
+        }
+    ));
+    spectrogram.add_controller(drag);
+
+    // Pinching zooms around the middle of the fingers
+    let pinch = gtk::GestureZoom::new();
+    let anchor = Rc::new(Cell::new(0.5));
+    pinch.connect_begin(clone!(
+        #[weak] spectrogram,
+        #[strong] start_range,
+        #[strong] anchor,
+        move |gesture, _| {
+            start_range.set(spectrogram.frequency_range());
+            let size = (spectrogram.width().max(1) as f32, spectrogram.height().max(1) as f32);
+            if let Some((x, y)) = gesture.bounding_box_center() {
+                anchor.set(spectrogram.orientation().fractions((x as f32, y as f32), size).1);
+            }
+        }
+    ));
+    pinch.connect_scale_changed(clone!(
+        #[weak] spectrogram,
+        #[strong] start_range,
+        #[strong] anchor,
+        move |_, scale| {
+            spectrogram.set_frequency_range(zoomed(spectrogram.frequency_scale(), start_range.get(), anchor.get(), 1.0 / scale.max(0.01) as f32));
+        }
+    ));
+    spectrogram.add_controller(pinch);
+
+    // Double-clicking the frequency axis goes back to the full range
+    let click = gtk::GestureClick::new();
+    click.connect_pressed(clone!(
+        #[weak] spectrogram,
+        move |gesture, n_press, x, y| {
+            let size = (spectrogram.width() as f32, spectrogram.height() as f32);
+            if !axes::in_frequency_strip(spectrogram.orientation(), (x as f32, y as f32), size) {
+                gesture.set_state(gtk::EventSequenceState::Denied);
+            } else if n_press == 2 {
+                spectrogram.reset_frequency_range();
+            }
+        }
+    ));
+    spectrogram.add_controller(click);
 }
 
 mod imp {
@@ -47,24 +224,88 @@ mod imp {
     use glium::texture::Texture2d;
     use glium::texture::{MipmapsOption, UncompressedFloatFormat};
     use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
-    use crate::fourier::audio_transform::AudioTransform;
     use super::*;
 
     #[derive(Properties)]
     #[properties(wrapper_type = super::GPUSpectrogram)]
     pub struct GPUSpectrogram {
         // FFT parameters
-        #[property(name = "sample-rate", set = Self::set_sample_rate, type = u32)]
-        pub fft: RefCell<AudioStreamTransform<FastFourierTransform>>,
-
-        #[property(set = Self::set_palette, type = ColorScheme)]
+        #[property(
+            name = "sample-rate", get = Self::sample_rate, set = Self::set_sample_rate, type = u32,
+            override_interface = Visualizer
+        )]
+        pub ring: RefCell<FrameRing>,
+        /// Set once a real input's rate arrives, replacing the placeholder transform
+        sample_rate_known: Cell<bool>,
+
+        #[property(get, set = Self::set_palette, override_interface = Visualizer)]
         pub palette: RefCell<ColorScheme>,
 
+        /// Freezes the display, while frames keep arriving in the scrollback
+        #[property(get, set = Self::set_paused, override_interface = Visualizer)]
+        pub paused: Cell<bool>,
+        /// While frozen, the newest frame shown (counted like `received`) and how many frames fit across the widget
+        pub view: Cell<Option<(i64, usize)>>,
+        /// A texture of the frames in `view`, with markers to go along with it; rebuilt whenever the view changes
+        review_textures: RefCell<Option<(Texture2d, Texture2d)>>,
+
+        /// The range of frequencies shown, from the bottom of the widget to the top
+        #[property(get, set = Self::set_min_frequency, minimum = LOWEST_FREQUENCY)]
+        pub min_frequency: Cell<f32>,
+        #[property(get, set = Self::set_max_frequency, minimum = LOWEST_FREQUENCY)]
+        pub max_frequency: Cell<f32>,
+        /// How frequencies are spread between the bottom and top of the widget
+        #[property(get, set = Self::set_frequency_scale, builder(FrequencyScale::default()))]
+        pub frequency_scale: Cell<FrequencyScale>,
+
+        /// The levels at either end of the palette, in decibels
+        #[property(get, set = Self::set_min_db)]
+        pub min_db: Cell<f32>,
+        #[property(get, set = Self::set_max_db)]
+        pub max_db: Cell<f32>,
+        /// Keeps adjusting the levels to fit the noise floor and peaks of what's on screen
+        #[property(get, set)]
+        pub auto_range: Cell<bool>,
+
+        /// How many seconds of history fit across the widget
+        #[property(get, set = Self::set_duration, minimum = 0.05, maximum = 600.0)]
+        pub duration: Cell<f32>,
+        /// How many frames are drawn for each second of input; lowered if the history would hold too many frames
+        #[property(get, set = Self::set_frame_rate, minimum = 1.0, maximum = 4000.0)]
+        pub frame_rate: Cell<f32>,
+
+        /// Which way the history scrolls
+        #[property(get, set = Self::set_orientation, builder(SpectrogramOrientation::default()))]
+        pub orientation: Cell<SpectrogramOrientation>,
+
+        /// Whether frequency and time axes are drawn over the spectrogram
+        #[property(get, set = Self::set_show_axes)]
+        pub show_axes: Cell<bool>,
+        /// Whether the axes' ticks are extended into lines across the whole spectrogram
+        #[property(get, set = Self::set_show_grid)]
+        pub show_grid: Cell<bool>,
+
+        /// Running count of gaps in the input; each increase marks the time axis
+        #[property(
+            name = "discontinuities", type = u64,
+            get = |imp: &Self| imp.markers.count(), set = |imp: &Self, count| imp.markers.set_count(count)
+        )]
+        markers: DiscontinuityMarkers,
+
+        /// A copy of the recent frames, so values can be read back for the readout and older ones scrolled back to
+        history: RefCell<FrameHistory>,
+        /// The number of frames added to the history since it was last cleared
+        received: Cell<i64>,
+        /// Set when the fft texture was resized, so it gets refilled from the history
+        refill: Cell<bool>,
+        level_tracker: RefCell<LevelTracker>,
+        pub pointer: Cell<Option<(f32, f32)>>,
+
         context: RefCell<Option<Rc<glium::backend::Context>>>,
         program: RefCell<Option<glium::Program>>,
         palette_texture: RefCell<Option<Texture2d>>,
-        fft_texture: RefCell<Option<Texture2d>>,
-        offset: Cell<usize>,
+        marker_texture: RefCell<Option<Texture2d>>,
+        frequency_texture: RefCell<Option<Texture2d>>,
     }
 
     #[glib::object_subclass]
@@ -73,21 +314,47 @@ mod imp {
         type Type = super::GPUSpectrogram;
         type ParentType = gtk::GLArea;
 
+        fn class_init(klass: &mut Self::Class) {
+            klass.install_action("spectrogram.reset-frequency-range", None, |spectrogram, _, _| {
+                spectrogram.reset_frequency_range();
+            });
+        }
+
         fn new() -> Self {
-            let (_, dummy_sample_stream) = HeapRb::new(1).split();
-            let fft = AudioStreamTransform::new(
-                dummy_sample_stream,
-                FastFourierTransform::new(100 as Frequency, 1 as Period),
-                1f32 / FRAMES_PER_SECOND,
+            let ring = FrameRing::new(
+                1f32 / DEFAULT_FRAME_RATE,
+                (DEFAULT_DURATION * DEFAULT_FRAME_RATE).round() as usize,
+                MipmapsOption::AutoGeneratedMipmaps,
             );
             Self {
-                fft: fft.into(),
+                ring: ring.into(),
+                sample_rate_known: false.into(),
                 palette: ColorScheme::new_mono(colorous::MAGMA, "magma").into(),
+                paused: false.into(),
+                view: None.into(),
+                review_textures: None.into(),
+                min_frequency: MIN_FREQUENCY.into(),
+                max_frequency: MAX_FREQUENCY.into(),
+                frequency_scale: FrequencyScale::default().into(),
+                min_db: DEFAULT_MIN_DB.into(),
+                max_db: DEFAULT_MAX_DB.into(),
+                auto_range: false.into(),
+                duration: DEFAULT_DURATION.into(),
+                frame_rate: DEFAULT_FRAME_RATE.into(),
+                orientation: SpectrogramOrientation::default().into(),
+                show_axes: true.into(),
+                show_grid: false.into(),
+                history: FrameHistory::new(MAX_FRAMES).into(),
+                received: 0.into(),
+                refill: false.into(),
+                level_tracker: Default::default(),
+                pointer: None.into(),
+                markers: DiscontinuityMarkers::default(),
                 context: None.into(),
                 program: None.into(),
                 palette_texture: None.into(),
-                fft_texture: None.into(),
-                offset: 0.into(),
+                marker_texture: None.into(),
+                frequency_texture: None.into(),
             }
         }
     }
@@ -138,38 +405,50 @@ mod imp {
                         uniform uint num_samples;
                         uniform uint offset;
 
-                        uniform float min_frequency;
-                        uniform float max_frequency;
+                        uniform float nyquist_frequency;
+                        uniform int orientation;
 
                         uniform float min_db;
                         uniform float max_db;
 
                         uniform sampler2D fft;
                         uniform sampler2D palette;
+                        uniform sampler2D markers;
+                        uniform sampler2D frequencies;
                         out vec4 f_color;
-                        void main() {
 
-                            float min_frequency = 32;
-                            float max_frequency = 22030;
-                            float frequency_range = max_frequency - min_frequency;
-                            float linear_frequency = uv.y * frequency_range + min_frequency;
-                            float linear_frequency_mapped = linear_frequency / max_frequency;
+                        // This matches SpectrogramOrientation::fractions, giving the time and frequency at a point
+                        vec2 fractions(vec2 point) {
+                            switch (orientation) {
+                                case 0: return point;
+                                case 1: return vec2(1.0 - point.x, point.y);
+                                case 2: return point.yx;
+                                default: return vec2(1.0 - point.y, point.x);
+                            }
+                        }
+
+                        void main() {
+                            vec2 position = fractions(uv);
 
-                            float log_min_frequency = log(min_frequency);
-                            float log_max_frequency = log(max_frequency);
-                            float log_frequency_range = log_max_frequency - log_min_frequency;
-                            float log_frequency = uv.y * log_frequency_range + log_min_frequency;
-                            float log_frequency_mapped = exp(log_frequency) / max_frequency;
+                            // The frequencies texture holds evenly spaced points along the frequency axis, from end to end
+                            float frequency_points = float(textureSize(frequencies, 0).x);
+                            float frequency = texture(frequencies, vec2((position.y * (frequency_points - 1.0) + 0.5) / frequency_points, 0.5)).r;
 
+                            // The fft texture spans everything up to the nyquist frequency
 
-                            // Log-scale coordinates
                             vec2 coord = vec2(
                                 // Time (with offset)
-                                (uv.x * num_samples + offset) / num_samples,
+                                (position.x * num_samples + offset) / num_samples,
                                 // Frequency
-                                log_frequency_mapped
+                                frequency / nyquist_frequency
                             );
 
+                            // Nothing above the nyquist frequency was measured, so the background shows through
+                            if (frequency > nyquist_frequency) {
+                                f_color = vec4(0.0);
+                                return;
+                            }
+
                             // Get magnitude
                             vec2 magnitude = texture(fft, coord.yx).rg;
 
@@ -184,6 +463,10 @@ mod imp {
                             // Get the appropriate color for this magnitude
                             f_color = texture(palette, vec2(pan, magnitude_db));
 
+                            // Highlight frames where the input had a gap
+                            float marker = texture(markers, vec2(0.5, coord.x)).r;
+                            f_color = mix(f_color, vec4(1.0), 0.6 * marker);
+
                             // Debugging
                             //f_color = texture(palette, uv);
 
@@ -199,9 +482,50 @@ mod imp {
         fn unrealize(&self) {
             self.context.replace(None);
             self.program.replace(None);
+            // Textures belong to the context, so they're all rebuilt (and the frames refilled from the history)
+            // if the widget is realized again, such as after moving to another pane
+            self.palette_texture.replace(None);
+            self.marker_texture.replace(None);
+            self.frequency_texture.replace(None);
+            self.review_textures.replace(None);
+            self.ring.borrow_mut().reset();
+            self.refill.set(true);
 
             self.parent_unrealize();
         }
+
+        fn snapshot(&self, snapshot: &gtk::Snapshot) {
+            self.parent_snapshot(snapshot);
+
+            // The axes are part of the snapshot (rather than separate widgets), so exported images include them
+            let widget = self.obj();
+            let (width, height) = (widget.width() as f32, widget.height() as f32);
+            let frequency_scale = self.frequency_scale.get();
+            let frequency_range = (self.min_frequency.get(), self.max_frequency.get());
+            let stride = self.ring.borrow().fft.stride;
+            let (newest, frames) = self.visible_frames();
+            let orientation = self.orientation.get();
+            if self.show_axes.get() {
+                axes::draw_axes(
+                    widget.upcast_ref(),
+                    snapshot,
+                    orientation,
+                    &axes::frequency_ticks(frequency_scale, frequency_range, orientation, (width, height)),
+                    &axes::time_ticks(newest as f32 * stride, frames as f32 * stride, orientation, (width, height)),
+                    self.show_grid.get(),
+                );
+            }
+
+            if let Some(pointer) = self.pointer.get() {
+                let (time, frequency) = orientation.fractions(pointer, (width, height));
+                let frames_ago = newest + ((1.0 - time) * frames as f32) as usize;
+                let frequency = frequency_scale.unmap(frequency, frequency_range);
+                let nyquist = self.ring.borrow().nyquist_frequency();
+                let magnitude = self.history.borrow().magnitude_at(frames_ago, frequency, nyquist);
+                let text = readout::readout_text(frequency, frames_ago as f32 * stride, magnitude);
+                readout::draw_crosshair(widget.upcast_ref(), snapshot, pointer, &text);
+            }
+        }
     }
 
     impl GLAreaImpl for GPUSpectrogram {
@@ -212,68 +536,105 @@ mod imp {
             let program = program_binding.as_ref().unwrap();
             let palette = self.palette.borrow();
             let bg_color = palette.background();
-            let mut fft = self.fft.borrow_mut();
+            let mut ring = self.ring.borrow_mut();
+            let nyquist_frequency = ring.nyquist_frequency();
+
+            // After a resize, the new texture starts out with whatever history it has room for
+            if ring.prepare(context) && self.refill.take() {
+                let history = self.history.borrow();
+                let rows = ring.rows();
+                ring.restore(history.iter()
+                    .skip(history.len().saturating_sub(rows))
+                    .map(<[StereoMagnitude]>::to_vec)
+                    .collect());
+            }
 
-            // Create fft texture if it's missing
-            if self.fft_texture.borrow().is_none() {
-                self.fft_texture.set(Texture2d::empty_with_format(
+            // The marker texture has one row for each row of the fft texture
+            if self.marker_texture.borrow().is_none() {
+                self.marker_texture.set(Texture2d::with_format(
                     context,
-                    UncompressedFloatFormat::F16F16,
-                    MipmapsOption::AutoGeneratedMipmaps,
-                    fft.transform.num_output_frequencies() as u32,
-                    //fft.transform.sample_rate() as u32,
-                    VIEWPORT_FRAMES as u32,
+                    vec![vec![0f32]; ring.rows()],
+                    UncompressedFloatFormat::F16,
+                    MipmapsOption::NoMipmap,
                 ).unwrap().into());
             }
-            let fft_texture_binding = self.fft_texture.borrow();
-            let fft_texture = fft_texture_binding.as_ref().unwrap();
+            let marker_texture_binding = self.marker_texture.borrow();
+            let marker_texture = marker_texture_binding.as_ref().unwrap();
 
-            // Create palette texture if it's missing
             if self.palette_texture.borrow().is_none() {
-                self.palette_texture.set(Texture2d::with_format(
-                    context,
-                    self.palette.borrow().lookup_table(32),
-                    UncompressedFloatFormat::F32F32F32F32,
-                    MipmapsOption::NoMipmap, // todo: are mipmaps necessary?
-                ).unwrap().into());
+                self.palette_texture.set(frame_ring::palette_texture(context, &palette).into());
             }
-            let palette_texture_binding = self.palette_texture.borrow_mut();
+            let palette_texture_binding = self.palette_texture.borrow();
             let palette_texture = palette_texture_binding.as_ref().unwrap();
 
-            // Rebind textures that may have been clobbered by a bug elsewhere
-            // (see: https://github.com/glium/glium/issues/2106)
-            unsafe {
-                // fixme: might not be necessary, a dummy texture might be enough to avoid the bug
-                // (see: https://github.com/glium/glium/issues/2106)
-                context.exec_with_context(|c| {
-                    c.state.texture_units.iter_mut().for_each(|t| *t = Default::default());
-                    epoxy::ActiveTexture(epoxy::TEXTURE0 + c.state.active_texture);
-                });
-            };
-
-            // Copy over new data
-            let mut stream = fft.process();
-            loop {
-                let current_index = self.offset.get();
-                let remaining_space = fft_texture.height() as usize - current_index;
-
-                let new_samples: Vec<_> = stream.by_ref().take(remaining_space).collect();
-                if new_samples.is_empty() {
-                    break;
+            // Frequencies are placed along the axis by FrequencyScale itself, rather than a copy of it in the shader
+            if self.frequency_texture.borrow().is_none() {
+                self.frequency_texture.set(Texture2d::empty_with_format(
+                    context,
+                    UncompressedFloatFormat::F32,
+                    MipmapsOption::NoMipmap,
+                    FREQUENCY_POINTS as u32,
+                    1,
+                ).unwrap().into());
+            }
+            let frequency_texture_binding = self.frequency_texture.borrow();
+            let frequency_texture = frequency_texture_binding.as_ref().unwrap();
+            let (frequency_scale, frequency_range) = (self.frequency_scale.get(), (self.min_frequency.get(), self.max_frequency.get()));
+            let frequencies: Vec<f32> = (0..FREQUENCY_POINTS)
+                .map(|point| frequency_scale.unmap(point as f32 / (FREQUENCY_POINTS - 1) as f32, frequency_range))
+                .collect();
+            frequency_texture.write(Rect { left: 0, bottom: 0, width: FREQUENCY_POINTS as u32, height: 1 }, vec![frequencies]);
+
+            frame_ring::rebind_texture_units(context);
+
+            // Copy over new data, keeping a copy in the history
+            ring.fill(|frames, row| {
+                let mut history = self.history.borrow_mut();
+                for frame in frames {
+                    history.push(frame);
+                }
+                self.received.set(self.received.get() + frames.len() as i64);
+                // Clear any markers left over from the last time around the ring
+                marker_texture.write(Rect {
+                    left: 0,
+                    bottom: row as u32,
+                    width: 1,
+                    height: frames.len() as u32,
+                }, vec![vec![0f32]; frames.len()]);
+            });
+            let fft_texture = ring.texture();
+
+            if self.auto_range.get() {
+                let levels = (self.min_db.get(), self.max_db.get());
+                let tracked = self.level_tracker.borrow_mut().track(&self.history.borrow(), self.visible_frames(), levels);
+                if let Some((min_db, max_db)) = tracked {
+                    self.obj().set_min_db(min_db);
+                    self.obj().set_max_db(max_db);
                 }
+            }
 
-                let block_size = new_samples.len();
-                let num_frequencies = new_samples[0].len();
-                // todo: reshape the texture if the number of frequencies changed
-                fft_texture.write(Rect {
+            // Mark the most recent frames if a gap was reported since the last draw
+            for row in self.markers.take_rows(ring.offset(), marker_texture.height() as usize) {
+                marker_texture.write(Rect {
                     left: 0,
-                    bottom: current_index as u32,
-                    width: num_frequencies as u32,
-                    height: block_size as u32,
-                }, new_samples);
-                self.offset.set((current_index + block_size) % fft_texture.height() as usize);
+                    bottom: row as u32,
+                    width: 1,
+                    height: 1,
+                }, vec![vec![1f32]]);
             }
 
+            // While frozen, the view is drawn from the scrollback instead of the ring, which keeps filling up behind it
+            if let Some((end, frames)) = self.view.get() {
+                if self.review_textures.borrow().is_none() {
+                    self.review_textures.set(Some(self.build_review_textures(context, end, frames, fft_texture.width())));
+                }
+            }
+            let review_textures = self.review_textures.borrow();
+            let (shown_fft_texture, shown_marker_texture, shown_offset) = match review_textures.as_ref() {
+                Some((fft_texture, marker_texture)) => (fft_texture, marker_texture, 0),
+                None => (fft_texture, marker_texture, ring.offset()),
+            };
+
             let params = glium::DrawParameters {
                 line_width: 2.0.into(),
                 smooth: Nicest.into(),
@@ -281,14 +642,23 @@ mod imp {
                 ..Default::default()
             };
 
-            let fft_sampler = fft_texture.sampled()
-                .wrap_function(SamplerWrapFunction::Repeat)
+            // Time wraps around the ring, but frequency stops at the top bin
+            let mut fft_sampler = shown_fft_texture.sampled()
                 .magnify_filter(MagnifySamplerFilter::Linear)
                 .minify_filter(MinifySamplerFilter::Linear);
+            fft_sampler.1.wrap_function = (SamplerWrapFunction::Clamp, SamplerWrapFunction::Repeat, SamplerWrapFunction::Repeat);
+            let marker_sampler = shown_marker_texture.sampled()
+                .wrap_function(SamplerWrapFunction::Repeat)
+                .magnify_filter(MagnifySamplerFilter::Nearest)
+                .minify_filter(MinifySamplerFilter::Nearest);
             let palette_sampler = palette_texture.sampled()
                 .wrap_function(SamplerWrapFunction::Clamp)
                 .magnify_filter(MagnifySamplerFilter::Linear)
                 .minify_filter(MinifySamplerFilter::Linear);
+            let frequency_sampler = frequency_texture.sampled()
+                .wrap_function(SamplerWrapFunction::Clamp)
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .minify_filter(MinifySamplerFilter::Linear);
 
             let mut frame = Frame::new(
                 context.clone(),
@@ -300,14 +670,16 @@ mod imp {
                 &glium::index::NoIndices(PrimitiveType::TrianglesList),
                 program,
                 &uniform! {
-                    num_samples: fft_texture.height(),
-                    offset: self.offset.get() as u32,
-                    min_frequency: 32.0,
-                    max_frequency: 22000.0,
-                    min_db: -70f32,
-                    max_db: -10f32,
+                    num_samples: shown_fft_texture.height(),
+                    offset: shown_offset as u32,
+                    nyquist_frequency: nyquist_frequency,
+                    orientation: self.orientation.get() as i32,
+                    min_db: self.min_db.get(),
+                    max_db: self.max_db.get(),
                     fft: fft_sampler,
                     palette: palette_sampler,
+                    markers: marker_sampler,
+                    frequencies: frequency_sampler,
                 },
                 &params,
             ).unwrap();
@@ -316,14 +688,223 @@ mod imp {
         }
     }
 
+    impl VisualizerImpl for GPUSpectrogram {
+        fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+            self.ring.borrow_mut().fft.input_stream = sample_stream;
+        }
+    }
+
     impl GPUSpectrogram {
+        pub fn sample_rate(&self) -> u32 {
+            self.ring.borrow().sample_rate()
+        }
+
         pub fn set_sample_rate(&self, sample_rate: u32) {
-            self.fft.borrow_mut().transform = FastFourierTransform::new(
-                sample_rate as Frequency,
-                0.05, // todo: this should be configurable!
-            );
-            // Force reconstruction of the fft texture to account for the new sample rate
-            self.fft_texture.set(None);
+            // A view of everything keeps showing everything, and any other view is kept below the new nyquist frequency
+            let showing_everything = self.max_frequency.get() >= 0.999 * self.highest_frequency();
+            self.ring.borrow_mut().set_sample_rate(sample_rate);
+            self.sample_rate_known.set(true);
+            // Frames have a bin for each frequency, so the scrollback holds fewer of them at higher rates
+            self.update_scrollback();
+            if showing_everything {
+                self.set_frequency_range((self.min_frequency.get(), self.highest_frequency()));
+            } else {
+                self.set_frequency_range((self.min_frequency.get(), self.max_frequency.get()));
+            }
+            self.clear_history();
+            self.marker_texture.set(None);
+        }
+
+        pub fn set_paused(&self, paused: bool) {
+            self.paused.set(paused);
+            // Freezing keeps the newest frames on screen, and anything arriving afterwards can still be scrolled to
+            self.view.set(paused.then(|| (self.received.get(), self.frames())));
+            self.review_textures.set(None);
+            self.obj().queue_draw();
+        }
+
+        /// How many frames back the right edge of the widget is, and how many frames fit across it.
+        pub fn visible_frames(&self) -> (usize, usize) {
+            match self.view.get() {
+                Some((end, frames)) => ((self.received.get() - end).max(0) as usize, frames),
+                None => (0, self.frames()),
+            }
+        }
+
+        /// Changes which part of the scrollback a frozen display shows, keeping it within the frames still in memory.
+        pub fn set_view(&self, end: i64, frames: usize) {
+            if self.view.get().is_none() || self.history.borrow().is_empty() { return; }
+            let received = self.received.get();
+            let available = self.history.borrow().len();
+            let frames = frames.min(available).max(MIN_FRAMES);
+            let end = end.max(received - available as i64 + frames as i64).min(received);
+            self.view.set(Some((end, frames)));
+            self.review_textures.set(None);
+            self.obj().queue_draw();
+        }
+
+        fn clear_history(&self) {
+            self.history.borrow_mut().clear();
+            self.received.set(0);
+            if self.view.get().is_some() {
+                self.view.set(Some((0, self.frames())));
+                self.review_textures.set(None);
+            }
+        }
+
+        /// Copies the frames in a view out of the scrollback, skipping some if there are more than a texture can hold.
+        fn build_review_textures(&self, context: &Rc<glium::backend::Context>, end: i64, frames: usize, width: u32) -> (Texture2d, Texture2d) {
+            let history = self.history.borrow();
+            let step = frames.div_ceil(MAX_FRAMES);
+            let rows = frames / step;
+            let newest = (self.received.get() - end).max(0) as usize;
+            let data: Vec<Vec<StereoMagnitude>> = (0..rows)
+                .map(|row| newest + frames - 1 - row * step)
+                .map(|frames_ago| match history.frame(frames_ago) {
+                    Some(frame) if frame.len() as u32 == width => frame.to_vec(),
+                    _ => vec![(0.0, 0.0); width as usize],
+                })
+                .collect();
+            let fft_texture = Texture2d::with_format(
+                context,
+                data,
+                UncompressedFloatFormat::F16F16,
+                MipmapsOption::AutoGeneratedMipmaps,
+            ).unwrap();
+            // Gaps aren't recorded in the scrollback, so there's nothing to mark
+            let marker_texture = Texture2d::with_format(
+                context,
+                vec![vec![0f32]; rows],
+                UncompressedFloatFormat::F16,
+                MipmapsOption::NoMipmap,
+            ).unwrap();
+            (fft_texture, marker_texture)
+        }
+
+        pub fn set_min_frequency(&self, frequency: f32) {
+            self.set_frequency_range((frequency, self.max_frequency.get()));
+        }
+
+        pub fn set_max_frequency(&self, frequency: f32) {
+            self.set_frequency_range((self.min_frequency.get(), frequency));
+        }
+
+        /// The highest frequency the input can contain.
+        pub fn highest_frequency(&self) -> f32 {
+            if self.sample_rate_known.get() {
+                self.ring.borrow().nyquist_frequency()
+            } else {
+                MAX_FREQUENCY
+            }
+        }
+
+        /// Clamps a range to the frequencies the input can contain, keeping at least a sliver of it in view.
+        pub fn set_frequency_range(&self, (min, max): (f32, f32)) {
+            let scale = self.frequency_scale.get();
+            let highest = scale.warp(self.highest_frequency());
+            let lowest = scale.warp(LOWEST_FREQUENCY);
+            let (mut min, mut max) = (scale.warp(min.max(LOWEST_FREQUENCY)), scale.warp(max.max(LOWEST_FREQUENCY)));
+            // The span is kept when the range hits a limit, so that panning doesn't zoom
+            let span = (max - min).clamp(MIN_SPAN_FRACTION * (highest - lowest), highest - lowest);
+            if min < lowest {
+                (min, max) = (lowest, lowest + span);
+            } else if min + span > highest {
+                (min, max) = (highest - span, highest);
+            } else {
+                max = min + span;
+            }
+            let (min, max) = (scale.unwarp(min), scale.unwarp(max));
+            if self.min_frequency.replace(min) != min {
+                self.obj().notify_min_frequency();
+            }
+            if self.max_frequency.replace(max) != max {
+                self.obj().notify_max_frequency();
+            }
+            self.obj().queue_draw();
+        }
+
+        /// The number of rows in the fft texture, enough for `duration` at `frame-rate` (within limits).
+        pub fn frames(&self) -> usize {
+            ((self.duration.get() * self.frame_rate.get()).round() as usize).clamp(MIN_FRAMES, MAX_FRAMES)
+        }
+
+        /// How many frames are taken each second: the chosen frame rate, unless the limits on `frames` forced another.
+        pub fn effective_frame_rate(&self) -> f32 {
+            let (duration, frame_rate) = (self.duration.get(), self.frame_rate.get());
+            let frames = (duration * frame_rate).round() as usize;
+            if (MIN_FRAMES..=MAX_FRAMES).contains(&frames) {
+                frame_rate
+            } else {
+                self.frames() as f32 / duration
+            }
+        }
+
+        pub fn set_duration(&self, duration: f32) {
+            self.duration.set(duration);
+            // Frames keep their spacing in time, so everything in the history can be shown again
+            self.resize(true);
+        }
+
+        pub fn set_frame_rate(&self, frame_rate: f32) {
+            self.frame_rate.set(frame_rate);
+            self.resize(false);
+        }
+
+        /// Rebuilds the ring texture with room for the current duration and frame rate.
+        fn resize(&self, keep_history: bool) {
+            let frame_rate = self.effective_frame_rate();
+            let stride = 1.0 / frame_rate;
+            // Frames which were taken at a different rate can't be shown alongside new ones,
+            // though rates which only differ by rounding are close enough
+            let previous_rate = 1.0 / self.ring.borrow().fft.stride;
+            let keep_history = keep_history && (frame_rate - previous_rate).abs() <= FRAME_RATE_TOLERANCE * frame_rate;
+            self.ring.borrow_mut().fft.stride = stride;
+            if !keep_history {
+                self.clear_history();
+            }
+            self.update_scrollback();
+            self.refill.set(keep_history);
+            self.ring.borrow_mut().set_rows(self.frames());
+            self.marker_texture.set(None);
+            self.obj().queue_draw();
+        }
+
+        /// Makes room in the history for the scrollback, or for everything on screen if that's more.
+        fn update_scrollback(&self) {
+            let fft = &self.ring.borrow().fft;
+            let frame_bytes = fft.transform.num_output_frequencies().max(1) * size_of::<StereoMagnitude>();
+            let scrollback = ((SCROLLBACK_SECONDS / fft.stride) as usize).min(SCROLLBACK_BYTES / frame_bytes);
+            self.history.borrow_mut().set_capacity(scrollback.max(self.frames()));
+        }
+
+        pub fn set_orientation(&self, orientation: SpectrogramOrientation) {
+            self.orientation.set(orientation);
+            self.obj().queue_draw();
+        }
+
+        pub fn set_frequency_scale(&self, frequency_scale: FrequencyScale) {
+            self.frequency_scale.set(frequency_scale);
+            self.obj().queue_draw();
+        }
+
+        pub fn set_min_db(&self, min_db: f32) {
+            self.min_db.set(min_db);
+            self.obj().queue_draw();
+        }
+
+        pub fn set_max_db(&self, max_db: f32) {
+            self.max_db.set(max_db);
+            self.obj().queue_draw();
+        }
+
+        pub fn set_show_axes(&self, show_axes: bool) {
+            self.show_axes.set(show_axes);
+            self.obj().queue_draw();
+        }
+
+        pub fn set_show_grid(&self, show_grid: bool) {
+            self.show_grid.set(show_grid);
+            self.obj().queue_draw();
         }
 
         pub fn set_palette(&self, palette: ColorScheme) {
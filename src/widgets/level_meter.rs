@@ -0,0 +1,156 @@
+use std::cell::{Cell, RefCell};
+
+use adw::gdk::RGBA;
+use adw::glib::{Object, Properties, ControlFlow::Continue};
+use gtk::{glib, graphene::Rect, prelude::*, subclass::prelude::*};
+use ringbuf::{HeapCons, HeapRb, traits::{Consumer, Split}};
+
+use crate::colorscheme::ColorScheme;
+use crate::fourier::StereoMagnitude;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt, VisualizerImpl};
+
+// The meter spans this many decibels below full scale
+const FLOOR_DB: f32 = -60.0;
+// Peak markers fall back towards the current level at this rate
+const PEAK_DECAY_DB_PER_SECOND: f32 = 20.0;
+const PEAK_MARKER_HEIGHT: f32 = 2.0;
+const BAR_SPACING: f32 = 2.0;
+
+glib::wrapper! {
+    pub struct LevelMeter(ObjectSubclass<imp::LevelMeter>)
+        @extends gtk::Widget,
+        @implements Visualizer;
+}
+
+impl LevelMeter {
+    pub fn new(sample_stream: HeapCons<StereoMagnitude>) -> LevelMeter {
+        let object: LevelMeter = Object::builder().build();
+        object.attach_input(sample_stream);
+        object.add_tick_callback(|meter, clock| {
+            meter.imp().update(clock.frame_time());
+            meter.queue_draw();
+            Continue
+        });
+        object
+    }
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    (20.0 * (amplitude + 1e-7).log10()).max(FLOOR_DB)
+}
+
+mod imp {
+    use super::*;
+
+    #[derive(Properties)]
+    #[properties(wrapper_type = super::LevelMeter)]
+    pub struct LevelMeter {
+        pub input_stream: RefCell<HeapCons<StereoMagnitude>>,
+
+        /// Levels are measured over whatever arrived since the last frame, so the rate isn't needed
+        #[property(get, set, override_interface = Visualizer)]
+        pub sample_rate: Cell<u32>,
+        #[property(get, set, override_interface = Visualizer)]
+        pub palette: RefCell<ColorScheme>,
+        #[property(get, set, override_interface = Visualizer)]
+        pub paused: Cell<bool>,
+
+        /// RMS level of the most recent frame, in dB, for each channel
+        levels: Cell<(f32, f32)>,
+        /// Slowly decaying peaks, in dB, for each channel
+        peaks: Cell<(f32, f32)>,
+        last_frame_time: Cell<i64>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for LevelMeter {
+        const NAME: &'static str = "LevelMeter";
+        type Type = super::LevelMeter;
+        type ParentType = gtk::Widget;
+
+        fn new() -> Self {
+            let (_, dummy_sample_stream) = HeapRb::new(1).split();
+            Self {
+                input_stream: dummy_sample_stream.into(),
+                sample_rate: 0.into(),
+                palette: ColorScheme::new_mono(colorous::MAGMA, "magma").into(),
+                paused: false.into(),
+                levels: (FLOOR_DB, FLOOR_DB).into(),
+                peaks: (FLOOR_DB, FLOOR_DB).into(),
+                last_frame_time: 0.into(),
+            }
+        }
+    }
+
+    #[glib::derived_properties]
+    impl ObjectImpl for LevelMeter {}
+
+    impl WidgetImpl for LevelMeter {
+        fn snapshot(&self, snapshot: &gtk::Snapshot) {
+            let width = self.obj().width() as f32;
+            let height = self.obj().height() as f32;
+            if width == 0.0 || height == 0.0 { return; }
+
+            let palette = self.palette.borrow();
+            let to_rgba = |color: colorous::Color| RGBA::new(
+                color.r as f32 / 255.0,
+                color.g as f32 / 255.0,
+                color.b as f32 / 255.0,
+                1.0,
+            );
+            let (background, foreground) = (to_rgba(palette.background()), to_rgba(palette.foreground()));
+            snapshot.append_color(&background, &Rect::new(0.0, 0.0, width, height));
+
+            // One bar for each channel, filling up from the bottom
+            let bar_width = ((width - BAR_SPACING * 3.0) / 2.0).max(1.0);
+            let (levels, peaks) = (self.levels.get(), self.peaks.get());
+            for (i, (level, peak)) in [(levels.0, peaks.0), (levels.1, peaks.1)].into_iter().enumerate() {
+                let x = BAR_SPACING + i as f32 * (bar_width + BAR_SPACING);
+                let level_height = height * (1.0 - level / FLOOR_DB);
+                snapshot.append_color(&foreground, &Rect::new(x, height - level_height, bar_width, level_height));
+                let peak_y = height * peak / FLOOR_DB;
+                snapshot.append_color(&foreground, &Rect::new(x, peak_y, bar_width, PEAK_MARKER_HEIGHT));
+            }
+        }
+    }
+
+    impl VisualizerImpl for LevelMeter {
+        fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+            self.input_stream.replace(sample_stream);
+        }
+    }
+
+    impl LevelMeter {
+        /// Measures everything which arrived since the previous frame.
+        pub fn update(&self, frame_time: i64) {
+            // While paused, the meter holds its last reading
+            if self.discard_input_if_paused(&mut self.input_stream.borrow_mut()) {
+                self.last_frame_time.set(frame_time);
+                return;
+            }
+
+            let (mut sum, mut count) = ((0.0, 0.0), 0usize);
+            for (l, r) in self.input_stream.borrow_mut().pop_iter() {
+                sum = (sum.0 + l * l, sum.1 + r * r);
+                count += 1;
+            }
+
+            // Frame times are in microseconds
+            let elapsed = (frame_time - self.last_frame_time.replace(frame_time)).max(0) as f32 / 1e6;
+            let decay = PEAK_DECAY_DB_PER_SECOND * elapsed.min(1.0);
+            let peaks = self.peaks.get();
+            // Without new input (for example while paused) the bars fall like the peaks do
+            let levels = if count > 0 {
+                (to_db((sum.0 / count as f32).sqrt()), to_db((sum.1 / count as f32).sqrt()))
+            } else {
+                let levels = self.levels.get();
+                ((levels.0 - decay).max(FLOOR_DB), (levels.1 - decay).max(FLOOR_DB))
+            };
+            self.levels.set(levels);
+            self.peaks.set((
+                (peaks.0 - decay).max(levels.0),
+                (peaks.1 - decay).max(levels.1),
+            ));
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+use crate::fourier::StereoMagnitude;
+use crate::fourier::frame_history::FrameHistory;
+use crate::widgets::readout::to_db;
+
+// Most of a spectrogram is background, so a low percentile lands on the noise floor
+const NOISE_FLOOR_PERCENTILE: f32 = 0.2;
+// A very high percentile (rather than the maximum) keeps single clicks from dimming everything else
+const PEAK_PERCENTILE: f32 = 0.999;
+const MIN_SPAN_DB: f32 = 20.0;
+// How far the range moves towards its target on each redraw, so that it doesn't flicker
+const SMOOTHING: f32 = 0.05;
+const MAX_SAMPLES: usize = 16384;
+// Finding the percentiles means sorting through the frames, so the target is only moved this often
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Moves a range of levels towards the noise floor and peaks of what's on screen.
+#[derive(Default)]
+pub struct LevelTracker {
+    /// Kept between updates, so the frames are sorted without allocating
+    samples: Vec<StereoMagnitude>,
+    target: Option<(f32, f32)>,
+    updated: Option<Instant>,
+}
+
+impl LevelTracker {
+    /// Moves a range of levels towards the noise floor and peaks of the `visible` frames in a history,
+    /// given as how many frames ago the newest one is and how many are shown.
+    pub fn track(&mut self, history: &FrameHistory, visible: (usize, usize), (min_db, max_db): (f32, f32)) -> Option<(f32, f32)> {
+        if self.updated.is_none_or(|updated| updated.elapsed() >= UPDATE_INTERVAL) {
+            self.updated = Some(Instant::now());
+            self.target = history
+                .percentiles(visible, NOISE_FLOOR_PERCENTILE, PEAK_PERCENTILE, MAX_SAMPLES, &mut self.samples)
+                .map(|(floor, peak)| {
+                    let (floor, peak) = (to_db(floor), to_db(peak));
+                    (floor, peak.max(floor + MIN_SPAN_DB))
+                });
+        }
+        let (floor, peak) = self.target?;
+        Some((min_db + (floor - min_db) * SMOOTHING, max_db + (peak - max_db) * SMOOTHING))
+    }
+}
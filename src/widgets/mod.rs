@@ -5,3 +5,15 @@ pub mod oscilloscope;
 pub mod gpu_spectrogram;
 pub mod glarea_backend;
 pub mod placeholder;
+pub mod playback_controls;
+pub mod level_meter;
+pub mod visualizer_kind;
+pub mod pane_layout;
+pub mod visualizer;
+pub mod axes;
+pub mod readout;
+pub mod level_range;
+pub mod spectrogram_orientation;
+pub mod waterfall_surface;
+pub mod discontinuity_markers;
+pub mod frame_ring;
@@ -6,6 +6,8 @@ use gtk::{gdk, glib, prelude::*};
 use ringbuf::{HeapCons, traits::Split};
 use crate::fourier::StereoMagnitude;
 use crate::widgets::glarea_backend::GLAreaBackend;
+use crate::widgets::frame_ring;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt, VisualizerImpl};
 use std::{cell::RefCell, rc::Rc};
 use std::cell::Cell;
 use std::iter::zip;
@@ -20,14 +22,14 @@ const BUFFER_SIZE: usize = 1024 * 16;
 
 glib::wrapper! {
     pub struct Oscilloscope(ObjectSubclass<imp::Oscilloscope>)
-        @extends gtk::GLArea, gtk::Widget;
+        @extends gtk::GLArea, gtk::Widget,
+        @implements Visualizer;
 }
 
 impl Oscilloscope {
     pub fn new(sample_stream: HeapCons<StereoMagnitude>) -> Oscilloscope {
-        let object = Object::builder().build();
-        let imp = imp::Oscilloscope::from_obj(&object);
-        imp.input_stream.replace(sample_stream);
+        let object: Oscilloscope = Object::builder().build();
+        object.attach_input(sample_stream);
         object.add_tick_callback(|oscilloscope, _| {
             if !oscilloscope.imp().input_stream.borrow().is_empty() {
                 oscilloscope.queue_draw();
@@ -58,11 +60,15 @@ mod imp {
     #[derive(Properties)]
     #[properties(wrapper_type = super::Oscilloscope)]
     pub struct Oscilloscope {
-        #[property(name = "sample-rate", set = Self::set_sample_rate, type = u32)]
         pub input_stream: RefCell<HeapCons<StereoMagnitude>>,
 
-        #[property(get, set)]
+        /// Only kept for the interface, since this visualizer doesn't depend on the sample rate
+        #[property(get, set, override_interface = Visualizer)]
+        pub sample_rate: Cell<u32>,
+        #[property(get, set, override_interface = Visualizer)]
         pub palette: RefCell<ColorScheme>,
+        #[property(get, set, override_interface = Visualizer)]
+        pub paused: Cell<bool>,
 
         context: RefCell<Option<Rc<glium::backend::Context>>>,
         program: RefCell<Option<glium::Program>>,
@@ -80,7 +86,9 @@ mod imp {
             let (_, dummy_sample_stream) = HeapRb::new(1).split();
             Self {
                 input_stream: dummy_sample_stream.into(),
+                sample_rate: 0.into(),
                 palette: ColorScheme::new_mono(colorous::MAGMA, "magma").into(),
+                paused: false.into(),
                 context: None.into(),
                 program: None.into(),
                 texture: None.into(),
@@ -180,22 +188,15 @@ mod imp {
             let mut texture_binding = self.texture.borrow_mut();
             let texture = texture_binding.as_mut().unwrap();
 
-            // Rebind textures that may have been clobbered by a bug elsewhere
-            // (see: https://github.com/glium/glium/issues/2106)
-            unsafe {
-                // fixme: might not be necessary, a dummy texture might be enough to avoid the bug
-                // (see: https://github.com/glium/glium/issues/2106)
-                context.exec_with_context(|c| {
-                    c.state.texture_units.iter_mut().for_each(|t| *t = Default::default());
-                    epoxy::ActiveTexture(epoxy::TEXTURE0 + c.state.active_texture);
-                });
-            };
+            frame_ring::rebind_texture_units(context);
 
             let mut frame = Frame::new(
                 context.clone(),
                 context.get_framebuffer_dimensions(),
             );
 
+            self.discard_input_if_paused(stream);
+
             while !stream.is_empty() {
                 let current_index = self.offset.get();
                 let remaining_space = texture.width() as usize - current_index;
@@ -257,9 +258,9 @@ mod imp {
         }
     }
 
-    impl Oscilloscope {
-        pub fn set_sample_rate(&self, sample_rate: u32) {
-            // This visualizer doesn't actually depend on the sample rate
+    impl VisualizerImpl for Oscilloscope {
+        fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+            self.input_stream.replace(sample_stream);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,626 @@
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+
+use adw::glib::{clone, Object, Properties};
+use adw::prelude::BinExt;
+use adw::subclass::prelude::*;
+use gtk::{gdk, glib, glib::translate::ToGlibPtr, prelude::*, GraphicsOffload, Orientation};
+
+use crate::colorscheme::{ColorScheme, DEFAULT_MAX_DB, DEFAULT_MIN_DB, MIN_DB_SPAN};
+use crate::devices::audio_input_list_model::AudioInputListModel;
+use crate::frequency_scale::FrequencyScale;
+use crate::widgets::gpu_spectrogram::{DEFAULT_DURATION, DEFAULT_FRAME_RATE};
+use crate::widgets::spectrogram_orientation::SpectrogramOrientation;
+use crate::widgets::spectrum_analyzer::{SpectrumAveraging, SpectrumStyle, DEFAULT_AVERAGING_TIME, DEFAULT_PEAK_DECAY};
+use crate::widgets::visualizer::{Visualizer, VisualizerExt};
+use crate::widgets::visualizer_kind::VisualizerKind;
+
+const STYLE: &str = "
+.visualizer-pane.active-pane {
+    outline: 2px solid alpha(@accent_color, 0.8);
+    outline-offset: -2px;
+}
+";
+
+glib::wrapper! {
+    pub struct PaneLayout(ObjectSubclass<imp::PaneLayout>)
+        @extends adw::Bin, gtk::Widget;
+}
+
+/// The visualizers in each row, with the proportions of the window given to each.
+#[derive(Debug, Clone)]
+struct LayoutConfig {
+    rows: Vec<Vec<VisualizerKind>>,
+    /// The fraction of the remaining height given to each row but the last
+    row_sizes: Vec<f64>,
+    /// The fraction of the remaining width given to each pane but the last, for each row
+    pane_sizes: Vec<Vec<f64>>,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self { rows: vec![vec![VisualizerKind::default()]], row_sizes: vec![], pane_sizes: vec![vec![]] }
+    }
+}
+
+/// One visualizer, along with the widget which is actually placed in the layout.
+struct Pane {
+    kind: VisualizerKind,
+    visualizer: Visualizer,
+    widget: gtk::Widget,
+}
+
+impl PaneLayout {
+    pub fn new(input: &AudioInputListModel) -> PaneLayout {
+        let object: PaneLayout = Object::builder().build();
+        object.imp().input.replace(Some(input.clone()));
+        object
+    }
+
+    /// Where the layout is kept between sessions.
+    pub fn config_path() -> PathBuf {
+        glib::user_config_dir().join("spectrogram-rs").join("layout.ini")
+    }
+
+    /// Restores the saved layout, or a single spectrogram if there isn't one.
+    pub fn load(&self) {
+        let config = Self::read_config().unwrap_or_else(|err| {
+            if !err.matches(glib::FileError::Noent) {
+                eprintln!("Failed to load the layout: {}", err);
+            }
+            LayoutConfig::default()
+        });
+        self.imp().set_config(config);
+    }
+
+    pub fn save(&self) -> Result<(), glib::Error> {
+        let config = self.imp().config();
+        let key_file = glib::KeyFile::new();
+        key_file.set_integer("Layout", "Rows", config.rows.len() as i32);
+        set_double_list(&key_file, "Layout", "Sizes", &config.row_sizes);
+        for (i, (row, sizes)) in config.rows.iter().zip(&config.pane_sizes).enumerate() {
+            let group = format!("Row {}", i);
+            set_string_list(&key_file, &group, "Panes", &row.iter().map(|kind| kind.id()).collect::<Vec<_>>());
+            set_double_list(&key_file, &group, "Sizes", sizes);
+        }
+
+        let path = Self::config_path();
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory).ok();
+        }
+        key_file.save_to_file(path)
+    }
+
+    fn read_config() -> Result<LayoutConfig, glib::Error> {
+        let key_file = glib::KeyFile::new();
+        key_file.load_from_file(Self::config_path(), glib::KeyFileFlags::NONE)?;
+        let mut config = LayoutConfig {
+            rows: vec![],
+            row_sizes: key_file.double_list("Layout", "Sizes").unwrap_or_default(),
+            pane_sizes: vec![],
+        };
+        for i in 0..key_file.integer("Layout", "Rows")? {
+            let group = format!("Row {}", i);
+            // Visualizers from newer versions are skipped, rather than discarding the whole layout
+            let row: Vec<_> = key_file.string_list(&group, "Panes")?.iter()
+                .filter_map(|id| VisualizerKind::from_id(id.as_str()))
+                .collect();
+            if row.is_empty() { continue; }
+            config.rows.push(row);
+            config.pane_sizes.push(key_file.double_list(&group, "Sizes").unwrap_or_default());
+        }
+        if config.rows.is_empty() {
+            return Ok(LayoutConfig::default());
+        }
+        Ok(config)
+    }
+
+    /// Adds a visualizer after the active pane, either beside it or in a new row below it.
+    pub fn add_pane(&self, kind: VisualizerKind, new_row: bool) {
+        let imp = self.imp();
+        let mut config = imp.config();
+        let (row, column) = imp.active.get();
+        // Sizes are reset, so that the new pane gets an equal share
+        config.row_sizes.clear();
+        config.pane_sizes.iter_mut().for_each(Vec::clear);
+        let active = if new_row {
+            config.rows.insert(row + 1, vec![kind]);
+            config.pane_sizes.insert(row + 1, vec![]);
+            (row + 1, 0)
+        } else {
+            config.rows[row].insert(column + 1, kind);
+            (row, column + 1)
+        };
+        imp.set_config_keeping_panes(config, |r, c| {
+            // Existing panes keep their visualizers, which shift over to make room
+            match new_row {
+                true if r > row + 1 => Some((r - 1, c)),
+                true if r == row + 1 => None,
+                false if r == row && c > column + 1 => Some((r, c - 1)),
+                false if r == row && c == column + 1 => None,
+                _ => Some((r, c)),
+            }
+        });
+        imp.set_active(active);
+    }
+
+    /// The visualizer which the header bar's controls apply to.
+    pub fn active_visualizer(&self) -> Option<Visualizer> {
+        let (row, column) = self.imp().active.get();
+        self.imp().rows.borrow().get(row)?.get(column).map(|pane| pane.visualizer.clone())
+    }
+
+    /// Removes the active pane, unless it's the only one left.
+    pub fn remove_active_pane(&self) {
+        let imp = self.imp();
+        if self.n_panes() <= 1 { return; }
+        let mut config = imp.config();
+        let (row, column) = imp.active.get();
+        config.row_sizes.clear();
+        config.pane_sizes.iter_mut().for_each(Vec::clear);
+        config.rows[row].remove(column);
+        let row_removed = config.rows[row].is_empty();
+        if row_removed {
+            config.rows.remove(row);
+            config.pane_sizes.remove(row);
+        }
+        imp.set_config_keeping_panes(config, |r, c| match row_removed {
+            true if r >= row => Some((r + 1, c)),
+            false if r == row && c >= column => Some((r, c + 1)),
+            _ => Some((r, c)),
+        });
+        let row = row.min(imp.rows.borrow().len() - 1);
+        let column = column.saturating_sub(1).min(imp.rows.borrow()[row].len() - 1);
+        imp.set_active((row, column));
+    }
+}
+
+// The bindings leave out KeyFile's list setters, so these call the C functions, which escape and separate
+// the values to match `string_list` and `double_list`
+fn set_string_list(key_file: &glib::KeyFile, group: &str, key: &str, values: &[&str]) {
+    let values = glib::StrV::from(values);
+    unsafe {
+        glib::ffi::g_key_file_set_string_list(
+            key_file.to_glib_none().0,
+            group.to_glib_none().0,
+            key.to_glib_none().0,
+            values.as_ptr() as *const *const _,
+            values.len(),
+        );
+    }
+}
+
+fn set_double_list(key_file: &glib::KeyFile, group: &str, key: &str, values: &[f64]) {
+    unsafe {
+        glib::ffi::g_key_file_set_double_list(
+            key_file.to_glib_none().0,
+            group.to_glib_none().0,
+            key.to_glib_none().0,
+            values.as_ptr() as *mut f64,
+            values.len(),
+        );
+    }
+}
+
+/// Places widgets side by side with draggable dividers between them, returning the dividers.
+fn nest(
+    children: Vec<gtk::Widget>,
+    orientation: Orientation,
+    sizes: &[f64],
+) -> (gtk::Widget, Vec<gtk::Paned>) {
+    let count = children.len();
+    let mut children = children.into_iter().rev();
+    let mut nested = children.next().expect("at least one pane");
+    let mut dividers = vec![];
+    for (i, child) in children.enumerate() {
+        let index = count - 2 - i;
+        let paned = gtk::Paned::builder()
+            .orientation(orientation)
+            .start_child(&child)
+            .end_child(&nested)
+            .build();
+        // Without a saved size, each child gets an equal share of the space
+        restore_position(&paned, sizes.get(index).copied().unwrap_or(1.0 / (count - index) as f64));
+        dividers.push(paned.clone());
+        nested = paned.upcast();
+    }
+    dividers.reverse();
+    (nested, dividers)
+}
+
+fn restore_position(paned: &gtk::Paned, fraction: f64) {
+    // The size isn't known until the divider is first allocated, and can't be changed during allocation
+    let restored = Cell::new(false);
+    paned.connect_max_position_notify(move |paned| {
+        if paned.max_position() > 0 && !restored.replace(true) {
+            glib::idle_add_local_once(clone!(
+                #[weak] paned,
+                move || paned.set_position((paned.max_position() as f64 * fraction).round() as i32)
+            ));
+        }
+    });
+}
+
+/// Display settings the layout holds for its panes, passed on to every new visualizer.
+const FORWARDED_PROPERTIES: [&str; 14] = [
+    "show-grid",
+    "frequency-scale",
+    "orientation",
+    "duration",
+    "frame-rate",
+    "min-db",
+    "max-db",
+    "auto-range",
+    "spectrum-style",
+    "averaging",
+    "averaging-time",
+    "peak-hold",
+    "peak-decay",
+    "max-hold",
+];
+
+/// Only some visualizers have axes or a range of levels, so the others are left alone.
+fn set_if_supported(visualizer: &Visualizer, name: &str, value: impl Into<glib::Value>) {
+    if visualizer.find_property(name).is_some() {
+        visualizer.set_property(name, value);
+    }
+}
+
+fn fraction(paned: &gtk::Paned) -> f64 {
+    paned.position() as f64 / paned.max_position().max(1) as f64
+}
+
+mod imp {
+    use super::*;
+
+    #[derive(Properties, Default)]
+    #[properties(wrapper_type = super::PaneLayout)]
+    pub struct PaneLayout {
+        pub(super) input: RefCell<Option<AudioInputListModel>>,
+
+        /// Shared by every visualizer in the layout
+        #[property(get, set = Self::set_palette)]
+        palette: RefCell<Option<ColorScheme>>,
+
+        /// Freezes every visualizer in the layout
+        #[property(get, set = Self::set_paused)]
+        paused: Cell<bool>,
+        /// Draws grid lines on the visualizers which have axes
+        #[property(get, set = Self::set_show_grid)]
+        show_grid: Cell<bool>,
+        /// How the spectrograms spread frequencies along their axes
+        #[property(get, set = Self::set_frequency_scale, builder(FrequencyScale::default()))]
+        frequency_scale: Cell<FrequencyScale>,
+        /// Which way the spectrograms scroll
+        #[property(get, set = Self::set_orientation, builder(SpectrogramOrientation::default()))]
+        orientation: Cell<SpectrogramOrientation>,
+        /// How much history the spectrograms show, and how finely it's divided
+        #[property(get, set = Self::set_duration)]
+        duration: Cell<f32>,
+        #[property(get, set = Self::set_frame_rate)]
+        frame_rate: Cell<f32>,
+        /// The range of levels shown by the spectrograms, in decibels
+        #[property(get, set = Self::set_min_db)]
+        min_db: Cell<f32>,
+        #[property(get, set = Self::set_max_db)]
+        max_db: Cell<f32>,
+        /// Lets the spectrograms choose their own range of levels
+        #[property(get, set = Self::set_auto_range)]
+        auto_range: Cell<bool>,
+        /// How the spectrum analyzers draw and smooth their traces
+        #[property(get, set = Self::set_spectrum_style, builder(SpectrumStyle::default()))]
+        spectrum_style: Cell<SpectrumStyle>,
+        #[property(get, set = Self::set_averaging, builder(SpectrumAveraging::default()))]
+        averaging: Cell<SpectrumAveraging>,
+        #[property(get, set = Self::set_averaging_time)]
+        averaging_time: Cell<f32>,
+        #[property(get, set = Self::set_peak_hold)]
+        peak_hold: Cell<bool>,
+        #[property(get, set = Self::set_peak_decay)]
+        peak_decay: Cell<f32>,
+        #[property(get, set = Self::set_max_hold)]
+        max_hold: Cell<bool>,
+
+        #[property(name = "n-panes", get = Self::n_panes, type = u32)]
+        pub(super) rows: RefCell<Vec<Vec<Pane>>>,
+        /// The pane which the header bar's controls apply to
+        #[property(
+            name = "active-kind", get = Self::active_kind, set = Self::set_active_kind,
+            type = VisualizerKind, builder(VisualizerKind::default())
+        )]
+        pub(super) active: Cell<(usize, usize)>,
+
+        row_dividers: RefCell<Vec<gtk::Paned>>,
+        pane_dividers: RefCell<Vec<Vec<gtk::Paned>>>,
+        frames: RefCell<Vec<Vec<adw::Bin>>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PaneLayout {
+        const NAME: &'static str = "PaneLayout";
+        type Type = super::PaneLayout;
+        type ParentType = adw::Bin;
+
+        fn new() -> Self {
+            Self {
+                duration: DEFAULT_DURATION.into(),
+                frame_rate: DEFAULT_FRAME_RATE.into(),
+                min_db: DEFAULT_MIN_DB.into(),
+                max_db: DEFAULT_MAX_DB.into(),
+                averaging_time: DEFAULT_AVERAGING_TIME.into(),
+                peak_hold: true.into(),
+                peak_decay: DEFAULT_PEAK_DECAY.into(),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[glib::derived_properties]
+    impl ObjectImpl for PaneLayout {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let provider = gtk::CssProvider::new();
+            provider.load_from_string(STYLE);
+            if let Some(display) = gdk::Display::default() {
+                gtk::style_context_add_provider_for_display(
+                    &display,
+                    &provider,
+                    gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+                );
+            }
+        }
+    }
+
+    impl WidgetImpl for PaneLayout {}
+
+    impl BinImpl for PaneLayout {}
+
+    impl PaneLayout {
+        fn n_panes(&self) -> u32 {
+            self.rows.borrow().iter().map(Vec::len).sum::<usize>() as u32
+        }
+
+        fn set_palette(&self, palette: Option<ColorScheme>) {
+            if let Some(palette) = &palette {
+                for pane in self.rows.borrow().iter().flatten() {
+                    pane.visualizer.set_palette(palette);
+                }
+            }
+            self.palette.replace(palette);
+        }
+
+        fn set_paused(&self, paused: bool) {
+            for pane in self.rows.borrow().iter().flatten() {
+                pane.visualizer.set_paused(paused);
+            }
+            self.paused.set(paused);
+        }
+
+        fn set_show_grid(&self, show_grid: bool) {
+            self.forward("show-grid", show_grid);
+            self.show_grid.set(show_grid);
+        }
+
+        fn set_frequency_scale(&self, frequency_scale: FrequencyScale) {
+            self.forward("frequency-scale", frequency_scale);
+            self.frequency_scale.set(frequency_scale);
+        }
+
+        fn set_orientation(&self, orientation: SpectrogramOrientation) {
+            self.forward("orientation", orientation);
+            self.orientation.set(orientation);
+        }
+
+        fn set_duration(&self, duration: f32) {
+            self.forward("duration", duration);
+            self.duration.set(duration);
+        }
+
+        fn set_frame_rate(&self, frame_rate: f32) {
+            self.forward("frame-rate", frame_rate);
+            self.frame_rate.set(frame_rate);
+        }
+
+        fn set_min_db(&self, min_db: f32) {
+            let min_db = min_db.min(self.max_db.get() - MIN_DB_SPAN);
+            self.forward("min-db", min_db);
+            self.min_db.set(min_db);
+        }
+
+        fn set_max_db(&self, max_db: f32) {
+            let max_db = max_db.max(self.min_db.get() + MIN_DB_SPAN);
+            self.forward("max-db", max_db);
+            self.max_db.set(max_db);
+        }
+
+        fn set_auto_range(&self, auto_range: bool) {
+            self.forward("auto-range", auto_range);
+            // Turning automatic ranging off goes back to the levels chosen in the header bar
+            if !auto_range {
+                self.forward("min-db", self.min_db.get());
+                self.forward("max-db", self.max_db.get());
+            }
+            self.auto_range.set(auto_range);
+        }
+
+        fn set_spectrum_style(&self, spectrum_style: SpectrumStyle) {
+            self.forward("spectrum-style", spectrum_style);
+            self.spectrum_style.set(spectrum_style);
+        }
+
+        fn set_averaging(&self, averaging: SpectrumAveraging) {
+            self.forward("averaging", averaging);
+            self.averaging.set(averaging);
+        }
+
+        fn set_averaging_time(&self, averaging_time: f32) {
+            self.forward("averaging-time", averaging_time);
+            self.averaging_time.set(averaging_time);
+        }
+
+        fn set_peak_hold(&self, peak_hold: bool) {
+            self.forward("peak-hold", peak_hold);
+            self.peak_hold.set(peak_hold);
+        }
+
+        fn set_peak_decay(&self, peak_decay: f32) {
+            self.forward("peak-decay", peak_decay);
+            self.peak_decay.set(peak_decay);
+        }
+
+        fn set_max_hold(&self, max_hold: bool) {
+            self.forward("max-hold", max_hold);
+            self.max_hold.set(max_hold);
+        }
+
+        /// Passes a display setting on to every pane that has it.
+        fn forward(&self, name: &str, value: impl Into<glib::Value>) {
+            let value = value.into();
+            for pane in self.rows.borrow().iter().flatten() {
+                set_if_supported(&pane.visualizer, name, value.clone());
+            }
+        }
+
+        pub(super) fn config(&self) -> LayoutConfig {
+            LayoutConfig {
+                rows: self.rows.borrow().iter()
+                    .map(|row| row.iter().map(|pane| pane.kind).collect())
+                    .collect(),
+                row_sizes: self.row_dividers.borrow().iter().map(fraction).collect(),
+                pane_sizes: self.pane_dividers.borrow().iter()
+                    .map(|dividers| dividers.iter().map(fraction).collect())
+                    .collect(),
+            }
+        }
+
+        /// Replaces every pane with a new visualizer.
+        pub(super) fn set_config(&self, config: LayoutConfig) {
+            self.set_config_keeping_panes(config, |_, _| None);
+            self.set_active((0, 0));
+        }
+
+        /// Rearranges the panes, reusing the visualizers of the panes which `previous` maps back to.
+        pub(super) fn set_config_keeping_panes(
+            &self,
+            config: LayoutConfig,
+            previous: impl Fn(usize, usize) -> Option<(usize, usize)>,
+        ) {
+            let input = self.input.borrow().clone().expect("the layout was created with an input");
+            let mut old_rows: Vec<Vec<Option<Pane>>> = self.rows.take().into_iter()
+                .map(|row| row.into_iter().map(Some).collect())
+                .collect();
+
+            // Detach the reused visualizers from their old frames, so they can be placed in new ones
+            for frame in self.frames.take().iter().flatten() {
+                frame.set_child(None::<&gtk::Widget>);
+            }
+
+            let rows: Vec<Vec<Pane>> = config.rows.iter().enumerate().map(|(r, row)| {
+                row.iter().enumerate().map(|(c, &kind)| {
+                    let reused = previous(r, c)
+                        .and_then(|(r, c)| old_rows.get_mut(r)?.get_mut(c)?.take())
+                        .filter(|pane| pane.kind == kind);
+                    reused.unwrap_or_else(|| self.build_pane(kind, &input))
+                }).collect()
+            }).collect();
+
+            // Anything which wasn't reused is dropped here, which ends its subscription to the input
+            drop(old_rows);
+
+            let mut frames = vec![];
+            let mut pane_dividers = vec![];
+            let row_widgets = rows.iter().enumerate().map(|(r, row)| {
+                let row_frames: Vec<adw::Bin> = row.iter().enumerate()
+                    .map(|(c, pane)| self.build_frame(r, c, pane))
+                    .collect();
+                let sizes = config.pane_sizes.get(r).map(Vec::as_slice).unwrap_or_default();
+                let (widget, dividers) = nest(
+                    row_frames.iter().map(|frame| frame.clone().upcast()).collect(),
+                    Orientation::Horizontal,
+                    sizes,
+                );
+                frames.push(row_frames);
+                pane_dividers.push(dividers);
+                widget
+            }).collect();
+            let (widget, row_dividers) = nest(row_widgets, Orientation::Vertical, &config.row_sizes);
+
+            self.rows.replace(rows);
+            self.frames.replace(frames);
+            self.pane_dividers.replace(pane_dividers);
+            self.row_dividers.replace(row_dividers);
+            self.obj().set_child(Some(&widget));
+            self.obj().notify_n_panes();
+        }
+
+        fn build_pane(&self, kind: VisualizerKind, input: &AudioInputListModel) -> Pane {
+            let visualizer = kind.build(input);
+            if let Some(palette) = self.palette.borrow().as_ref() {
+                visualizer.set_palette(palette);
+            }
+            visualizer.set_paused(self.paused.get());
+            for name in FORWARDED_PROPERTIES {
+                set_if_supported(&visualizer, name, self.obj().property_value(name));
+            }
+            let widget = if kind.is_offloadable() {
+                let offload = GraphicsOffload::new(Some(&visualizer));
+                offload.set_black_background(true);
+                offload.upcast()
+            } else {
+                visualizer.clone().upcast()
+            };
+            Pane { kind, visualizer, widget }
+        }
+
+        fn build_frame(&self, row: usize, column: usize, pane: &Pane) -> adw::Bin {
+            let frame = adw::Bin::builder()
+                .child(&pane.widget)
+                .css_classes(["visualizer-pane"])
+                .build();
+            // Clicking a pane makes it the target of the header bar's pane controls
+            let click = gtk::GestureClick::builder()
+                .propagation_phase(gtk::PropagationPhase::Capture)
+                .build();
+            click.connect_pressed(clone!(
+                #[weak(rename_to = layout)] self.obj(),
+                move |_, _, _, _| layout.imp().set_active((row, column))
+            ));
+            frame.add_controller(click);
+            frame
+        }
+
+        fn active_kind(&self) -> VisualizerKind {
+            let (row, column) = self.active.get();
+            self.rows.borrow().get(row).and_then(|row| row.get(column)).map(|pane| pane.kind).unwrap_or_default()
+        }
+
+        /// Swaps the visualizer in the active pane for a new one, leaving the rest of the layout alone.
+        fn set_active_kind(&self, kind: VisualizerKind) {
+            if kind == self.active_kind() { return; }
+            let (row, column) = self.active.get();
+            let Some(frame) = self.frames.borrow().get(row).and_then(|row| row.get(column)).cloned() else { return };
+            let input = self.input.borrow().clone().expect("the layout was created with an input");
+
+            // The new visualizer has its own subscription, and the old one's ends when it's dropped
+            let pane = self.build_pane(kind, &input);
+            frame.set_child(Some(&pane.widget));
+            self.rows.borrow_mut()[row][column] = pane;
+        }
+
+        pub(super) fn set_active(&self, active: (usize, usize)) {
+            self.active.set(active);
+            self.obj().notify_active_kind();
+            // The highlight is only useful when there's more than one pane to choose from
+            let highlight = self.n_panes() > 1;
+            for (r, row) in self.frames.borrow().iter().enumerate() {
+                for (c, frame) in row.iter().enumerate() {
+                    if highlight && (r, c) == active {
+                        frame.add_css_class("active-pane");
+                    } else {
+                        frame.remove_css_class("active-pane");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,29 +1,32 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use ringbuf::{HeapCons, HeapRb, traits::{Consumer, Split}};
 use adw::glib;
 use adw::glib::{Object, Properties, ControlFlow::Continue};
 use gtk::subclass::button::ButtonImpl;
-use adw::subclass::prelude::{ObjectImpl, ObjectSubclass, ObjectSubclassExt, ObjectSubclassIsExt, WidgetImpl, WidgetImplExt, DerivedObjectProperties};
-use gtk::prelude::{WidgetExtManual, ObjectExt, ButtonExt};
+use adw::subclass::prelude::{ObjectImpl, ObjectSubclass, ObjectSubclassIsExt, WidgetImpl, DerivedObjectProperties};
+use gtk::prelude::{WidgetExtManual, ButtonExt};
 use itertools::Itertools;
 
 use crate::fourier::StereoMagnitude;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt, VisualizerImpl};
 
 glib::wrapper! {
     pub struct PlaceholderVisualizer(ObjectSubclass<imp::PlaceholderVisualizer>)
-        @extends gtk::Button, gtk::Widget;
+        @extends gtk::Button, gtk::Widget,
+        @implements Visualizer;
 }
 
 impl PlaceholderVisualizer {
     pub fn new(sample_stream: HeapCons<StereoMagnitude>) -> Self {
-        let object = Object::builder().build();
-        let imp = imp::PlaceholderVisualizer::from_obj(&object);
+        let object: Self = Object::builder().build();
         object.add_tick_callback(|w, _| {
             let v = w.imp().input_stream.borrow_mut().pop_iter().collect_vec();
-            w.set_label(format!("{}", v.len()).as_str());
+            if !w.imp().paused.get() {
+                w.set_label(format!("{}", v.len()).as_str());
+            }
             Continue
         });
-        imp.input_stream.replace(sample_stream);
+        object.attach_input(sample_stream);
         object
     }
 }
@@ -35,11 +38,14 @@ mod imp {
     #[derive(Properties)]
     #[properties(wrapper_type = super::PlaceholderVisualizer)]
     pub struct PlaceholderVisualizer {
-        #[property(name = "sample-rate", set = Self::set_sample_rate, type = u32)]
         pub input_stream: RefCell<HeapCons<StereoMagnitude>>,
 
-        #[property(get, set)]
+        #[property(get, set, override_interface = Visualizer)]
+        pub sample_rate: Cell<u32>,
+        #[property(get, set, override_interface = Visualizer)]
         pub palette: RefCell<ColorScheme>,
+        #[property(get, set, override_interface = Visualizer)]
+        pub paused: Cell<bool>,
     }
 
     #[glib::object_subclass]
@@ -52,7 +58,9 @@ mod imp {
             let (_, dummy_sample_stream) = HeapRb::new(1).split();
             Self {
                 input_stream: dummy_sample_stream.into(),
+                sample_rate: 0.into(),
                 palette: ColorScheme::new_mono(colorous::MAGMA, "magma").into(),
+                paused: false.into(),
             }
         }
     }
@@ -64,9 +72,9 @@ mod imp {
 
     impl ButtonImpl for PlaceholderVisualizer {}
 
-    impl PlaceholderVisualizer {
-        pub fn set_sample_rate(&self, sample_rate: u32) {
-            // todo!()
+    impl VisualizerImpl for PlaceholderVisualizer {
+        fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+            self.input_stream.replace(sample_stream);
         }
     }
 }
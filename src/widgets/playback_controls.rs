@@ -0,0 +1,172 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use gtk::{
+    glib,
+    glib::{clone, ControlFlow, Object, Propagation},
+    prelude::*,
+    subclass::prelude::*,
+    Orientation,
+};
+
+use crate::sources::file_source::FilePlayback;
+
+glib::wrapper! {
+    pub struct PlaybackControls(ObjectSubclass<imp::PlaybackControls>)
+        @extends gtk::Box, gtk::Widget,
+        @implements gtk::Orientable;
+}
+
+impl PlaybackControls {
+    pub fn new() -> PlaybackControls {
+        Object::builder().build()
+    }
+
+    /// Attaches the controls to a playing file, or hides them if there isn't one.
+    pub fn set_playback(&self, playback: Option<Arc<FilePlayback>>, duration: Option<f64>) {
+        let imp = imp::PlaybackControls::from_obj(self);
+
+        // Detach first, so that syncing the buttons doesn't feed back into the old file
+        imp.playback.replace(None);
+        if let Some(playback) = &playback {
+            imp.play_button.set_active(!playback.paused.load(Ordering::Relaxed));
+            imp.loop_button.set_active(playback.looping.load(Ordering::Relaxed));
+            imp.fast_button.set_active(!playback.realtime.load(Ordering::Relaxed));
+        }
+
+        // Seeking is only possible if the length of the file is known
+        imp.seek_scale.set_range(0.0, duration.unwrap_or(1.0));
+        imp.seek_scale.set_value(0.0);
+        imp.seek_scale.set_sensitive(duration.is_some());
+
+        self.set_visible(playback.is_some());
+        imp.playback.replace(playback);
+    }
+}
+
+mod imp {
+    use super::*;
+
+    pub struct PlaybackControls {
+        pub playback: RefCell<Option<Arc<FilePlayback>>>,
+        pub play_button: gtk::ToggleButton,
+        pub seek_scale: gtk::Scale,
+        pub loop_button: gtk::ToggleButton,
+        pub fast_button: gtk::ToggleButton,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PlaybackControls {
+        const NAME: &'static str = "SpectrogramPlaybackControls";
+        type Type = super::PlaybackControls;
+        type ParentType = gtk::Box;
+
+        fn new() -> Self {
+            Self {
+                playback: None.into(),
+                play_button: gtk::ToggleButton::builder()
+                    .icon_name("media-playback-start-symbolic")
+                    .tooltip_text("Play")
+                    .build(),
+                seek_scale: gtk::Scale::builder()
+                    .orientation(Orientation::Horizontal)
+                    .draw_value(false)
+                    .width_request(160)
+                    .build(),
+                loop_button: gtk::ToggleButton::builder()
+                    .icon_name("media-playlist-repeat-symbolic")
+                    .tooltip_text("Loop")
+                    .build(),
+                fast_button: gtk::ToggleButton::builder()
+                    .icon_name("media-seek-forward-symbolic")
+                    .tooltip_text("Play as fast as possible")
+                    .build(),
+            }
+        }
+    }
+
+    impl ObjectImpl for PlaybackControls {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+            obj.set_spacing(4);
+            obj.set_visible(false);
+            obj.append(&self.play_button);
+            obj.append(&self.seek_scale);
+            obj.append(&self.loop_button);
+            obj.append(&self.fast_button);
+
+            self.play_button.connect_toggled(clone!(
+                #[weak(rename_to = this)] self,
+                move |button| {
+                    let playing = button.is_active();
+                    button.set_icon_name(
+                        if playing { "media-playback-pause-symbolic" } else { "media-playback-start-symbolic" }
+                    );
+                    button.set_tooltip_text(Some(if playing { "Pause" } else { "Play" }));
+                    if let Some(playback) = this.playback() {
+                        // Playing a finished file starts it over
+                        if playing && playback.finished.load(Ordering::Relaxed) {
+                            playback.seek(0.0);
+                        }
+                        playback.paused.store(!playing, Ordering::Relaxed);
+                    }
+                }
+            ));
+            self.loop_button.connect_toggled(clone!(
+                #[weak(rename_to = this)] self,
+                move |button| {
+                    if let Some(playback) = this.playback() {
+                        playback.looping.store(button.is_active(), Ordering::Relaxed);
+                    }
+                }
+            ));
+            self.fast_button.connect_toggled(clone!(
+                #[weak(rename_to = this)] self,
+                move |button| {
+                    if let Some(playback) = this.playback() {
+                        playback.realtime.store(!button.is_active(), Ordering::Relaxed);
+                    }
+                }
+            ));
+            // Only changes made by the user are forwarded, so the position updates below don't cause seeks
+            self.seek_scale.connect_change_value(clone!(
+                #[weak(rename_to = this)] self,
+                #[upgrade_or] Propagation::Proceed,
+                move |_, _, value| {
+                    if let Some(playback) = this.playback() {
+                        playback.seek(value);
+                    }
+                    Propagation::Proceed
+                }
+            ));
+
+            // Keep the controls in sync with the playback thread
+            glib::timeout_add_local(Duration::from_millis(100), clone!(
+                #[weak(rename_to = this)] self,
+                #[upgrade_or] ControlFlow::Break,
+                move || {
+                    if let Some(playback) = this.playback() {
+                        this.seek_scale.set_value(playback.position());
+                        if playback.finished.load(Ordering::Relaxed) && this.play_button.is_active() {
+                            this.play_button.set_active(false);
+                        }
+                    }
+                    ControlFlow::Continue
+                }
+            ));
+        }
+    }
+
+    impl PlaybackControls {
+        fn playback(&self) -> Option<Arc<FilePlayback>> {
+            self.playback.borrow().clone()
+        }
+    }
+
+    impl WidgetImpl for PlaybackControls {}
+
+    impl BoxImpl for PlaybackControls {}
+}
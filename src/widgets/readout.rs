@@ -0,0 +1,76 @@
+use gtk::{gdk::RGBA, glib, glib::clone, graphene::{Point, Rect}, prelude::*};
+
+use crate::fourier::{Frequency, StereoMagnitude};
+
+const NOTE_NAMES: [&str; 12] = ["C", "C♯", "D", "D♯", "E", "F", "F♯", "G", "G♯", "A", "A♯", "B"];
+const LABEL_OFFSET: f32 = 12.0;
+const LABEL_PADDING: f32 = 4.0;
+
+/// The nearest note in twelve-tone equal temperament (with A4 at 440 Hz), and how far off it is in cents.
+pub fn nearest_note(frequency: Frequency) -> (String, f32) {
+    let midi = 69.0 + 12.0 * (frequency / 440.0).log2();
+    let nearest = midi.round();
+    let name = NOTE_NAMES[(nearest as i32).rem_euclid(12) as usize];
+    let octave = (nearest as i32).div_euclid(12) - 1;
+    (format!("{}{}", name, octave), (midi - nearest) * 100.0)
+}
+
+/// Uses the same conversion as the shader, so the readout matches the colors on screen.
+pub fn to_db((l, r): StereoMagnitude) -> f32 {
+    10.0 * (l * l + r * r + 1e-7).log10()
+}
+
+/// Describes the point under the pointer, one value per line.
+pub fn readout_text(frequency: Frequency, seconds_ago: f32, magnitude: Option<StereoMagnitude>) -> String {
+    let frequency_text = if frequency >= 1000.0 {
+        format!("{:.2} kHz", frequency / 1000.0)
+    } else {
+        format!("{:.1} Hz", frequency)
+    };
+    let (note, cents) = nearest_note(frequency);
+    let level_text = magnitude.map(|m| format!("{:.1} dB", to_db(m))).unwrap_or_else(|| "–".into());
+    format!("{}\n{} {:+.0}¢\n-{:.2} s\n{}", frequency_text, note, cents, seconds_ago, level_text)
+}
+
+/// Remembers where the pointer is over a widget, and redraws the widget whenever it moves.
+pub fn track_pointer(widget: &impl IsA<gtk::Widget>, on_change: impl Fn(Option<(f32, f32)>) + 'static) {
+    let widget = widget.upcast_ref::<gtk::Widget>();
+    let on_change = std::rc::Rc::new(on_change);
+    let motion = gtk::EventControllerMotion::new();
+    motion.connect_motion(clone!(
+        #[weak] widget,
+        #[strong] on_change,
+        move |_, x, y| {
+            on_change(Some((x as f32, y as f32)));
+            widget.queue_draw();
+        }
+    ));
+    motion.connect_leave(clone!(
+        #[weak] widget,
+        move |_| {
+            on_change(None);
+            widget.queue_draw();
+        }
+    ));
+    widget.add_controller(motion);
+}
+
+/// Draws lines through the pointer, with a label beside it which stays inside the widget.
+pub fn draw_crosshair(widget: &gtk::Widget, snapshot: &gtk::Snapshot, (x, y): (f32, f32), text: &str) {
+    let (width, height) = (widget.width() as f32, widget.height() as f32);
+    let line_color = RGBA::new(1.0, 1.0, 1.0, 0.5);
+    snapshot.append_color(&line_color, &Rect::new(0.0, y, width, 1.0));
+    snapshot.append_color(&line_color, &Rect::new(x, 0.0, 1.0, height));
+
+    let layout = widget.create_pango_layout(Some(text));
+    let (text_width, text_height) = layout.pixel_size();
+    let (box_width, box_height) = (text_width as f32 + 2.0 * LABEL_PADDING, text_height as f32 + 2.0 * LABEL_PADDING);
+    // The label flips to the other side of the pointer when it would run off the edge
+    let box_x = if x + LABEL_OFFSET + box_width > width { x - LABEL_OFFSET - box_width } else { x + LABEL_OFFSET };
+    let box_y = if y + LABEL_OFFSET + box_height > height { y - LABEL_OFFSET - box_height } else { y + LABEL_OFFSET };
+    snapshot.append_color(&RGBA::new(0.0, 0.0, 0.0, 0.7), &Rect::new(box_x, box_y, box_width, box_height));
+    snapshot.save();
+    snapshot.translate(&Point::new(box_x + LABEL_PADDING, box_y + LABEL_PADDING));
+    snapshot.append_layout(&layout, &RGBA::WHITE);
+    snapshot.restore();
+}
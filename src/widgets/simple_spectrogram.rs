@@ -2,14 +2,11 @@ use std::sync::{Arc, Mutex};
 use std::cell::RefCell;
 use ringbuf::{HeapCons, HeapRb, traits::Split};
 use async_channel::Receiver;
-use plotters::coord::types::RangedCoordf32;
 use std::cell::Cell;
 use adw::gdk::RGBA;
 use gtk::graphene::Rect;
 use gtk::gsk::ScalingFilter;
 use gtk::prelude::SnapshotExt;
-use plotters::coord::ReverseCoordTranslate;
-use plotters::prelude::Cartesian2d;
 use crate::fourier::{FrequencySample, Period};
 
 use adw::{glib, glib::{Properties, Object}, gdk::gdk_pixbuf::{Pixbuf, Colorspace}, prelude::ObjectExt, subclass::prelude::{ObjectImpl, WidgetImpl, ObjectSubclass, DerivedObjectProperties}, gdk};
@@ -17,33 +14,43 @@ use adw;
 use adw::gdk::Texture;
 use adw::glib::ControlFlow::Continue;
 use adw::prelude::BinExt;
-use adw::subclass::prelude::ObjectSubclassExt;
+use adw::subclass::prelude::{ObjectSubclassExt, ObjectSubclassIsExt};
 use cpal::StreamConfig;
 use gtk::{ContentFit, Picture};
-use gtk::prelude::{WidgetExt, WidgetExtManual};
+use gtk::prelude::{Cast, WidgetExt, WidgetExtManual};
 
 use crate::{
-    colorscheme::ColorScheme,
-    log_scaling::{LogCoordf64, IntoReversibleLogRange},
+    colorscheme::{ColorScheme, DEFAULT_MAX_DB, DEFAULT_MIN_DB},
+    frequency_scale::{self, FrequencyScale},
     fourier::Frequency,
-    fourier::fft::FastFourierTransform,
+    fourier::fft::{DEFAULT_WINDOW_PERIOD, FastFourierTransform},
     fourier::audio_transform::AudioStreamTransform,
 };
 use crate::fourier::StereoMagnitude;
+use crate::fourier::frame_history::FrameHistory;
+use crate::widgets::discontinuity_markers::DiscontinuityMarkers;
+use crate::widgets::level_range::LevelTracker;
+use crate::widgets::readout;
+use crate::widgets::spectrogram_orientation::SpectrogramOrientation;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt, VisualizerImpl};
 
 const TEXTURE_WIDTH: i32 = 1024;
 const TEXTURE_HEIGHT: i32 = 1024;
 
 glib::wrapper! {
     pub struct SimpleSpectrogram(ObjectSubclass<imp::SimpleSpectrogram>)
-        @extends gtk::Widget;
+        @extends gtk::Widget,
+        @implements Visualizer;
 }
 
 impl SimpleSpectrogram {
     pub fn new(sample_stream: HeapCons<StereoMagnitude>) -> SimpleSpectrogram {
-        let object = Object::builder().build();
-        let imp = imp::SimpleSpectrogram::from_obj(&object);
-        imp.fft.borrow_mut().input_stream = sample_stream;
+        let object: SimpleSpectrogram = Object::builder().build();
+        object.attach_input(sample_stream);
+        readout::track_pointer(&object, glib::clone!(
+            #[weak] object,
+            move |pointer| object.imp().pointer.set(pointer)
+        ));
         object.add_tick_callback(|spectrogram, _| {
             // todo: only draw if there are unprocessed samples!
             spectrogram.queue_draw();
@@ -64,17 +71,45 @@ mod imp {
     #[properties(wrapper_type = super::SimpleSpectrogram)]
     pub struct SimpleSpectrogram {
         // Appearance settings
-        pub x_range: RangedCoordf32,
-        pub y_range: LogCoordf64,
-        #[property(get, set)]
+        /// How frequencies are spread between the bottom and top of the widget; only new columns use a changed scale
+        #[property(get, set, builder(FrequencyScale::default()))]
+        pub frequency_scale: Cell<FrequencyScale>,
+        /// Which way the history scrolls
+        #[property(get, set, builder(SpectrogramOrientation::default()))]
+        pub orientation: Cell<SpectrogramOrientation>,
+        #[property(get, set, override_interface = Visualizer)]
         pub palette: RefCell<ColorScheme>,
+        #[property(get, set, override_interface = Visualizer)]
+        pub paused: Cell<bool>,
+        /// The levels at either end of the palette, in decibels; only new columns are drawn with a changed range
+        #[property(get, set)]
+        pub min_db: Cell<f32>,
+        #[property(get, set)]
+        pub max_db: Cell<f32>,
+        /// Keeps adjusting the levels to fit the noise floor and peaks of what's on screen
+        #[property(get, set)]
+        pub auto_range: Cell<bool>,
+
+        /// Running count of gaps in the input; each increase marks the time axis
+        #[property(
+            name = "discontinuities", type = u64,
+            get = |imp: &Self| imp.markers.count(), set = |imp: &Self, count| imp.markers.set_count(count)
+        )]
+        markers: DiscontinuityMarkers,
 
         // Plot buffer
         pub buffer: Pixbuf,
         offset: Cell<usize>,
+        /// The frames behind each column of the buffer, so values can be read back for the readout
+        history: RefCell<FrameHistory>,
+        level_tracker: RefCell<LevelTracker>,
+        pub pointer: Cell<Option<(f32, f32)>>,
 
         // FFT parameters
-        #[property(name = "sample-rate", set = Self::set_sample_rate, type = u32)]
+        #[property(
+            name = "sample-rate", get = Self::sample_rate, set = Self::set_sample_rate, type = u32,
+            override_interface = Visualizer
+        )]
         pub fft: RefCell<AudioStreamTransform<FastFourierTransform>>,
         // todo: period, stride, etc.
     }
@@ -103,11 +138,19 @@ mod imp {
             );
 
             Self {
-                x_range: (-10.0..0.0).into(),
-                y_range: (32.0..22030.0).reversible_log_scale().base(2.0).zero_point(0.0).into(),
+                frequency_scale: FrequencyScale::default().into(),
+                orientation: SpectrogramOrientation::default().into(),
                 palette: palette.into(),
+                paused: false.into(),
+                min_db: DEFAULT_MIN_DB.into(),
+                max_db: DEFAULT_MAX_DB.into(),
+                auto_range: false.into(),
+                markers: DiscontinuityMarkers::default(),
                 buffer: buffer.unwrap(),
                 offset: 0.into(),
+                history: FrameHistory::new(TEXTURE_WIDTH as usize).into(),
+                level_tracker: Default::default(),
+                pointer: None.into(),
                 fft: fft.into(),
             }
         }
@@ -126,30 +169,38 @@ mod imp {
             let start_time = std::time::Instant::now();
             let buffer = &self.buffer;
 
-            let cartesian_range: Cartesian2d<RangedCoordf32, LogCoordf64> = Cartesian2d::new(
-                self.x_range.clone(),
-                self.y_range.clone(),
-                (0..buffer.width(), 0..buffer.height()),
-            );
+            let frequency_scale = self.frequency_scale.get();
+            let frequency_range = frequency_scale::default_range(self.fft.borrow().transform.sample_rate() / 2.0);
+
+            self.discard_input_if_paused(&mut self.fft.borrow_mut().input_stream);
+
+            if self.auto_range.get() {
+                let levels = (self.min_db.get(), self.max_db.get());
+                let tracked = self.level_tracker.borrow_mut().track(&self.history.borrow(), (0, TEXTURE_WIDTH as usize), levels);
+                if let Some((min_db, max_db)) = tracked {
+                    self.obj().set_min_db(min_db);
+                    self.obj().set_max_db(max_db);
+                }
+            }
+            let db_range = self.min_db.get()..self.max_db.get();
 
             let sample_rate = self.fft.borrow().transform.sample_rate();
             for frequency_sample in self.fft.borrow_mut().process() {
+                self.history.borrow_mut().push(&frequency_sample);
                 let frequency_sample = InterpolatedFrequencySample::new(
                     frequency_sample, SampleRate(sample_rate as u32)
                 );
                 let px = self.offset.get();
                 for py in 0..buffer.height() {
-                    let (_, f0) = cartesian_range.reverse_translate((buffer.width() - 1, py)).unwrap();
-                    let (_, f1) = cartesian_range.reverse_translate((buffer.width() - 1, py + 1)).unwrap();
+                    let f0 = frequency_scale.unmap(py as f32 / buffer.height() as f32, frequency_range);
+                    let f1 = frequency_scale.unmap((py + 1) as f32 / buffer.height() as f32, frequency_range);
 
-                    let frequency_range = (f0 as Frequency)..(f1 as Frequency);
-
-                    let magnitude = frequency_sample.magnitude_in(frequency_range);
+                    let magnitude = frequency_sample.magnitude_in(f0..f1);
                     // let magnitude = to_scaled_decibels(&magnitude);
 
                     let py = buffer.height() - py - 1;
 
-                    let (color, alpha) = self.palette.borrow().color_for(magnitude);
+                    let (color, alpha) = self.palette.borrow().color_in_range(magnitude, db_range.clone());
                     buffer.put_pixel(
                         px as u32,
                         py as u32,
@@ -164,6 +215,14 @@ mod imp {
                 self.offset.set((px + 1) % self.buffer.width() as usize);
             }
 
+            // Mark the most recent columns if a gap was reported since the last draw
+            let marker_color = self.palette.borrow().foreground();
+            for px in self.markers.take_rows(self.offset.get(), buffer.width() as usize) {
+                for py in 0..buffer.height() {
+                    buffer.put_pixel(px as u32, py as u32, marker_color.r, marker_color.g, marker_color.b, 255);
+                }
+            }
+
             // Draw the background
             let window_bounds = Rect::new(0.0, 0.0, width, height);
             let background_color = self.palette.borrow().background();
@@ -177,6 +236,12 @@ mod imp {
                 &window_bounds,
             );
 
+            // The buffer is drawn with time running to the right, then turned to match the orientation
+            let orientation = self.orientation.get();
+            snapshot.save();
+            snapshot.transform_matrix(&orientation.transform((width, height)));
+            let (width, height) = orientation.axis_lengths((width, height));
+
             // Swap the sides of the oscilloscope buffer, turning it into a scrolling view
             let window_space_offset = width * (self.offset.get() as f32 / TEXTURE_WIDTH as f32);
             if (self.buffer.width() - self.offset.get() as i32) > 0 {
@@ -207,15 +272,40 @@ mod imp {
                     ),
                 );
             };
+
+            snapshot.restore();
+
+            if let Some(pointer) = self.pointer.get() {
+                // The newest column is at the end of the time axis, and the buffer spans all of it
+                let (time, frequency) = orientation.fractions(pointer, (self.obj().width() as f32, self.obj().height() as f32));
+                let frames_ago = ((1.0 - time) * TEXTURE_WIDTH as f32) as usize;
+                let frequency = frequency_scale.unmap(frequency, frequency_range);
+                let fft = self.fft.borrow();
+                let nyquist = fft.transform.sample_rate() / 2.0;
+                let magnitude = self.history.borrow().magnitude_at(frames_ago, frequency, nyquist);
+                let text = readout::readout_text(frequency, frames_ago as f32 * fft.stride, magnitude);
+                readout::draw_crosshair(self.obj().upcast_ref(), snapshot, pointer, &text);
+            }
+        }
+    }
+
+    impl VisualizerImpl for SimpleSpectrogram {
+        fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+            self.fft.borrow_mut().input_stream = sample_stream;
         }
     }
 
     impl SimpleSpectrogram {
+        pub fn sample_rate(&self) -> u32 {
+            self.fft.borrow().transform.sample_rate() as u32
+        }
+
         pub fn set_sample_rate(&self, sample_rate: u32) {
             self.fft.borrow_mut().transform = FastFourierTransform::new(
                 sample_rate as Frequency,
-                0.05, // todo: this should be configurable!
-            )
+                DEFAULT_WINDOW_PERIOD,
+            );
+            self.history.borrow_mut().clear();
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,78 @@
+use gtk::{glib, graphene};
+
+/// Which way time flows across a spectrogram, named after the direction the history scrolls in.
+///
+/// Positions are described as fractions along each axis: time goes from 0 for the oldest frame to 1 for the newest,
+/// and frequency goes from 0 at the bottom of the range to 1 at the top.
+/// The fragment shader in `GPUSpectrogram` has its own copy of `fractions`, which must be kept in sync.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "SpectrogramOrientation")]
+pub enum SpectrogramOrientation {
+    #[default]
+    RightToLeft = 0,
+    LeftToRight = 1,
+    /// A waterfall, with the newest frame at the top
+    TopToBottom = 2,
+    BottomToTop = 3,
+}
+
+impl SpectrogramOrientation {
+    pub const ALL: [SpectrogramOrientation; 4] = [Self::RightToLeft, Self::LeftToRight, Self::TopToBottom, Self::BottomToTop];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RightToLeft => "Right to Left",
+            Self::LeftToRight => "Left to Right",
+            Self::TopToBottom => "Waterfall",
+            Self::BottomToTop => "Bottom to Top",
+        }
+    }
+
+    pub fn position(&self) -> u32 {
+        Self::ALL.iter().position(|orientation| orientation == self).unwrap() as u32
+    }
+
+    /// Whether time runs up or down the widget, leaving frequency to run across it.
+    pub fn is_vertical(&self) -> bool {
+        matches!(self, Self::TopToBottom | Self::BottomToTop)
+    }
+
+    /// The lengths of the time and frequency axes in a widget of the given size.
+    pub fn axis_lengths(&self, (width, height): (f32, f32)) -> (f32, f32) {
+        if self.is_vertical() { (height, width) } else { (width, height) }
+    }
+
+    /// Finds the time and frequency fractions at a point in a widget.
+    pub fn fractions(&self, (x, y): (f32, f32), (width, height): (f32, f32)) -> (f32, f32) {
+        let (x, y) = (x / width, y / height);
+        match self {
+            Self::RightToLeft => (x, 1.0 - y),
+            Self::LeftToRight => (1.0 - x, 1.0 - y),
+            Self::TopToBottom => (1.0 - y, x),
+            Self::BottomToTop => (y, x),
+        }
+    }
+
+    /// The point in a widget at the given time and frequency fractions.
+    pub fn point(&self, (time, frequency): (f32, f32), (width, height): (f32, f32)) -> (f32, f32) {
+        let (x, y) = match self {
+            Self::RightToLeft => (time, 1.0 - frequency),
+            Self::LeftToRight => (1.0 - time, 1.0 - frequency),
+            Self::TopToBottom => (frequency, 1.0 - time),
+            Self::BottomToTop => (frequency, time),
+        };
+        (x * width, y * height)
+    }
+
+    /// Maps an image drawn with time running to the right and frequency running up onto a widget of the given size.
+    ///
+    /// The image should be drawn with the size given by `axis_lengths`.
+    pub fn transform(&self, (width, height): (f32, f32)) -> graphene::Matrix {
+        match self {
+            Self::RightToLeft => graphene::Matrix::from_2d(1.0, 0.0, 0.0, 1.0, 0.0, 0.0),
+            Self::LeftToRight => graphene::Matrix::from_2d(-1.0, 0.0, 0.0, 1.0, width as f64, 0.0),
+            Self::TopToBottom => graphene::Matrix::from_2d(0.0, -1.0, -1.0, 0.0, width as f64, height as f64),
+            Self::BottomToTop => graphene::Matrix::from_2d(0.0, 1.0, -1.0, 0.0, width as f64, 0.0),
+        }
+    }
+}
@@ -1,120 +1,497 @@
-use std::iter::{zip};
-use fftw::types::c32;
-
-use gtk::{
-    glib,
-    prelude::*,
-    subclass::prelude::*,
-    LevelBar,
-    Orientation,
-};
-use glib::{
-    Object,
-};
-
-use num_traits::{Pow};
-use crate::fourier::{Frequency, FrequencySample};
-// use crate::fourier::{Frequency, FrequencySample}
-// use crate::frequency_sample::{Frequency, FrequencySample};
-
-fn log_space(start: f32, end: f32, n: usize, base: f32) -> impl Iterator<Item=f32> + Clone {
-    // println!("{}, {}", start, end);
-    let start = start.log(base);
-    let end = end.log(base);
-    // println!("-> {}, {}", start, end);
-    let step = (end - start) / n as f32;
-
-    let mut i = 0;
-    std::iter::from_fn(move || {
-        if i > n { () }
-
-        let linear_value = start + (step * i as f32);
-        i = i + 1;
-        // println!("{}, {}", linear_value, base.pow(linear_value));
-        Some(base.pow(linear_value))
-    })
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use adw::gdk::RGBA;
+use adw::glib::{clone, Object, Properties, ControlFlow::Continue};
+use gtk::{glib, graphene::Rect, gsk, prelude::*, subclass::prelude::*};
+use ringbuf::{HeapCons, HeapRb, traits::Split};
+
+use crate::colorscheme::{ColorScheme, DEFAULT_MAX_DB, DEFAULT_MIN_DB};
+use crate::fourier::{Frequency, Period, StereoMagnitude, fft::{DEFAULT_WINDOW_PERIOD, FastFourierTransform}, audio_transform::AudioStreamTransform};
+use crate::frequency_scale::{self, FrequencyScale};
+use crate::widgets::axes;
+use crate::widgets::spectrogram_orientation::SpectrogramOrientation;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt, VisualizerImpl};
+
+// New frames are produced at roughly the display's frame rate
+const FRAME_PERIOD: Period = 1.0 / 60.0;
+// The line is drawn with a point every few pixels, and bars are spread across the width
+const LINE_STEP: f32 = 2.0;
+const BAR_COUNT: usize = 64;
+const BAR_SPACING: f32 = 1.0;
+const PEAK_MARKER_HEIGHT: f32 = 2.0;
+pub const DEFAULT_AVERAGING_TIME: f32 = 0.2;
+pub const DEFAULT_PEAK_DECAY: f32 = 20.0;
+
+/// How the spectrum is drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "SpectrumStyle")]
+pub enum SpectrumStyle {
+    #[default]
+    Line = 0,
+    Bars = 1,
 }
 
-glib::wrapper! {
-    pub struct SpectrumAnalyzer(ObjectSubclass<imp::SpectrumAnalyzer>)
-        @extends gtk::Box, gtk::Widget;
+impl SpectrumStyle {
+    pub const ALL: [SpectrumStyle; 2] = [Self::Line, Self::Bars];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Line => "Line",
+            Self::Bars => "Bars",
+        }
+    }
+
+    pub fn position(&self) -> u32 {
+        Self::ALL.iter().position(|style| style == self).unwrap() as u32
+    }
 }
 
-impl SpectrumAnalyzer {
-    pub fn new() -> SpectrumAnalyzer {
-        Object::builder().build()
+/// How successive frames are combined before they're shown, to steady a noisy spectrum.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "SpectrumAveraging")]
+pub enum SpectrumAveraging {
+    None = 0,
+    /// Each frame is blended into a running average, so older frames fade out gradually
+    #[default]
+    Exponential = 1,
+    /// Every frame within the averaging time counts equally
+    Linear = 2,
+}
+
+impl SpectrumAveraging {
+    pub const ALL: [SpectrumAveraging; 3] = [Self::None, Self::Exponential, Self::Linear];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Exponential => "Exponential",
+            Self::Linear => "Linear",
+        }
     }
 
-    pub fn push_frequencies(&mut self, frequency_sample: &dyn FrequencySample) {
-        let min = -70.0;
-        let max = -10.0;
-        let self_ = imp::SpectrumAnalyzer::from_obj(self);
+    pub fn position(&self) -> u32 {
+        Self::ALL.iter().position(|averaging| averaging == self).unwrap() as u32
+    }
+}
 
-        let frequencies = log_space(
-            32.0,
-            frequency_sample.frequencies().end.max(22050.0),
-            self_.level_bars.len() + 1,
-            10.0,
-        );
-        let frequency_ranges = zip(frequencies.clone(), frequencies.skip(1));
+/// The averaged level, decaying peaks and all-time maximum of each frequency bin, in decibels.
+///
+/// The buffers are kept from one frame to the next, so nothing is allocated once they've grown to fit.
+#[derive(Default)]
+pub struct SpectrumTraces {
+    /// Power of each bin in the newest frame
+    frame_power: Vec<f32>,
+    /// Averaged power of each bin
+    power: Vec<f32>,
+    /// Frames within the averaging time, and their sum, for linear averaging
+    window: VecDeque<Vec<f32>>,
+    window_sum: Vec<f32>,
+    pub levels: Vec<f32>,
+    pub peaks: Vec<f32>,
+    pub max_hold: Vec<f32>,
+}
 
-        for (bar, (frequency_start, frequency_end)) in self_.level_bars.iter().zip(frequency_ranges) {
-            let (l, r) = frequency_sample.magnitude_in(frequency_start as Frequency..frequency_end as Frequency);
-            let magnitude = c32::new(l, r).norm();
-            let magnitude = 10.0 * (magnitude + 1e-7).log10();
-            let magnitude = ((magnitude - min) / (max - min)) as f64;
+impl SpectrumTraces {
+    /// Adds a frame which arrived `period` seconds after the previous one.
+    pub fn push(&mut self, frame: &[StereoMagnitude], period: Period, averaging: SpectrumAveraging, averaging_time: f32, peak_decay: f32) {
+        // A change in the number of bins (from a new sample rate) starts everything again
+        if frame.len() != self.power.len() {
+            *self = Self::default();
+        }
+        let first = self.power.is_empty();
+        self.frame_power.clear();
+        self.frame_power.extend(frame.iter().map(|(l, r)| l * l + r * r));
 
-            bar.set_value(magnitude.max(bar.value() * 0.99));
+        match averaging {
+            SpectrumAveraging::None => self.power.clone_from(&self.frame_power),
+            SpectrumAveraging::Exponential => {
+                let weight = if first { 1.0 } else { 1.0 - (-period / averaging_time.max(period)).exp() };
+                self.power.resize(frame.len(), 0.0);
+                for (average, power) in self.power.iter_mut().zip(&self.frame_power) {
+                    *average += weight * (power - *average);
+                }
+            }
+            SpectrumAveraging::Linear => {
+                let length = (averaging_time / period).round().max(1.0) as usize;
+                self.window_sum.resize(frame.len(), 0.0);
+                // The oldest frames make way for the new one, which takes over the last one's buffer
+                let mut spare = None;
+                while self.window.len() >= length {
+                    let oldest = self.window.pop_front().unwrap();
+                    for (sum, power) in self.window_sum.iter_mut().zip(&oldest) {
+                        // Rounding errors could otherwise leave a sum slightly below zero
+                        *sum = (*sum - power).max(0.0);
+                    }
+                    spare = Some(oldest);
+                }
+                let mut newest = spare.unwrap_or_default();
+                newest.clone_from(&self.frame_power);
+                for (sum, power) in self.window_sum.iter_mut().zip(&newest) {
+                    *sum += power;
+                }
+                self.window.push_back(newest);
+                let count = self.window.len() as f32;
+                self.power.clear();
+                self.power.extend(self.window_sum.iter().map(|sum| sum / count));
+            }
+        }
+        // Only the running average survives a change of averaging mode
+        if averaging != SpectrumAveraging::Linear {
+            self.window.clear();
+            self.window_sum.clear();
         }
+
+        // Uses the same conversion as the spectrograms, so levels line up between them
+        self.levels.clear();
+        self.levels.extend(self.power.iter().map(|power| 10.0 * (power + 1e-7).log10()));
+        let decay = peak_decay * period;
+        if first {
+            self.peaks.clone_from(&self.levels);
+            self.max_hold.clone_from(&self.levels);
+        } else {
+            for ((peak, max), level) in self.peaks.iter_mut().zip(self.max_hold.iter_mut()).zip(&self.levels) {
+                *peak = (*peak - decay).max(*level);
+                *max = max.max(*level);
+            }
+        }
+    }
+
+    pub fn reset_max_hold(&mut self) {
+        self.max_hold.clone_from(&self.levels);
+    }
+}
+
+/// The loudest level between two frequencies, in a trace spanning everything up to the nyquist frequency.
+///
+/// Ranges narrower than a bin are interpolated between the neighbouring bins instead.
+fn level_between(trace: &[f32], (f0, f1): (Frequency, Frequency), nyquist: Frequency) -> Option<f32> {
+    let last = trace.len().checked_sub(1)?;
+    let to_index = |frequency: Frequency| (frequency / nyquist * trace.len() as Frequency).clamp(0.0, last as f32);
+    let (start, end) = (to_index(f0), to_index(f1));
+    if end.floor() > start.ceil() {
+        trace[start.ceil() as usize..=end.floor() as usize].iter().copied().reduce(f32::max)
+    } else {
+        let center = (start + end) / 2.0;
+        let (below, above) = (trace[center.floor() as usize], trace[(center.ceil() as usize).min(last)]);
+        Some(below + (above - below) * center.fract())
+    }
+}
+
+glib::wrapper! {
+    /// The current spectrum as a line or bars, with optional peak hold and max hold traces.
+    pub struct SpectrumAnalyzer(ObjectSubclass<imp::SpectrumAnalyzer>)
+        @extends gtk::Widget,
+        @implements Visualizer;
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(sample_stream: HeapCons<StereoMagnitude>) -> SpectrumAnalyzer {
+        let object: SpectrumAnalyzer = Object::builder().build();
+        object.attach_input(sample_stream);
+        object.add_tick_callback(|analyzer, _| {
+            analyzer.imp().update();
+            analyzer.queue_draw();
+            Continue
+        });
+
+        // Double-clicking starts the max hold trace again
+        let click = gtk::GestureClick::new();
+        click.connect_pressed(clone!(
+            #[weak] object,
+            move |_, n_press, _, _| if n_press == 2 { object.reset_max_hold() }
+        ));
+        object.add_controller(click);
+        object
+    }
+
+    pub fn reset_max_hold(&self) {
+        self.imp().traces.borrow_mut().reset_max_hold();
+        self.queue_draw();
     }
 }
 
 mod imp {
+    use crate::fourier::audio_transform::AudioTransform;
     use super::*;
 
-    #[derive(Default)]
+    #[derive(Properties)]
+    #[properties(wrapper_type = super::SpectrumAnalyzer)]
     pub struct SpectrumAnalyzer {
-        pub level_bars: Vec<LevelBar>,
+        #[property(
+            name = "sample-rate", get = Self::sample_rate, set = Self::set_sample_rate, type = u32,
+            override_interface = Visualizer
+        )]
+        pub fft: RefCell<AudioStreamTransform<FastFourierTransform>>,
+        #[property(get, set, override_interface = Visualizer)]
+        pub palette: RefCell<ColorScheme>,
+        #[property(get, set, override_interface = Visualizer)]
+        pub paused: Cell<bool>,
+
+        // Appearance settings
+        /// How frequencies are spread across the widget
+        #[property(get, set, builder(FrequencyScale::default()))]
+        pub frequency_scale: Cell<FrequencyScale>,
+        /// The levels at the bottom and top of the widget, in decibels
+        #[property(get, set)]
+        pub min_db: Cell<f32>,
+        #[property(get, set)]
+        pub max_db: Cell<f32>,
+        #[property(get, set, builder(SpectrumStyle::default()))]
+        pub spectrum_style: Cell<SpectrumStyle>,
+
+        // Trace settings
+        #[property(get, set, builder(SpectrumAveraging::default()))]
+        pub averaging: Cell<SpectrumAveraging>,
+        /// How long a window the averaging covers, in seconds
+        #[property(get, set, minimum = 0.01)]
+        pub averaging_time: Cell<f32>,
+        /// Shows the recent peak of each frequency, falling at `peak-decay` decibels per second
+        #[property(get, set)]
+        pub peak_hold: Cell<bool>,
+        #[property(get, set, minimum = 0.0)]
+        pub peak_decay: Cell<f32>,
+        /// Shows the highest level of each frequency since the trace was last reset
+        #[property(get, set)]
+        pub max_hold: Cell<bool>,
+
+        pub traces: RefCell<SpectrumTraces>,
     }
 
-    // The central trait for subclassing a GObject
     #[glib::object_subclass]
     impl ObjectSubclass for SpectrumAnalyzer {
         const NAME: &'static str = "SpectrogramSpectrumAnalyzer";
         type Type = super::SpectrumAnalyzer;
-        type ParentType = gtk::Box;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.install_action("spectrum.reset-max-hold", None, |analyzer, _, _| {
+                analyzer.reset_max_hold();
+            });
+        }
 
         fn new() -> Self {
+            let (_, dummy_sample_stream) = HeapRb::new(1).split();
+            let fft = AudioStreamTransform::new(
+                dummy_sample_stream,
+                FastFourierTransform::new(100 as Frequency, 1 as Period),
+                FRAME_PERIOD,
+            );
             Self {
-                level_bars: (0..128).map(
-                    |_| LevelBar::builder()
-                        .hexpand(true)
-                        .vexpand(true)
-                        .value(0.3)
-                        .orientation(Orientation::Vertical)
-                        .inverted(true)
-                        .build()
-                ).collect()
+                fft: fft.into(),
+                palette: ColorScheme::new_mono(colorous::MAGMA, "magma").into(),
+                paused: false.into(),
+                frequency_scale: FrequencyScale::default().into(),
+                min_db: DEFAULT_MIN_DB.into(),
+                max_db: DEFAULT_MAX_DB.into(),
+                spectrum_style: SpectrumStyle::default().into(),
+                averaging: SpectrumAveraging::default().into(),
+                averaging_time: DEFAULT_AVERAGING_TIME.into(),
+                peak_hold: true.into(),
+                peak_decay: DEFAULT_PEAK_DECAY.into(),
+                max_hold: false.into(),
+                traces: SpectrumTraces::default().into(),
             }
         }
     }
 
-    // Trait shared by all GObjects
-    impl ObjectImpl for SpectrumAnalyzer {
-        fn constructed(&self) {
-            self.parent_constructed();
-            self.obj().set_spacing(1);
-            self.obj().set_margin_bottom(4);
-            self.obj().set_margin_top(4);
-            self.obj().set_margin_start(4);
-            self.obj().set_margin_end(4);
-            for bar in self.level_bars.iter() { self.obj().append(bar) };
+    #[glib::derived_properties]
+    impl ObjectImpl for SpectrumAnalyzer {}
+
+    impl WidgetImpl for SpectrumAnalyzer {
+        fn snapshot(&self, snapshot: &gtk::Snapshot) {
+            let width = self.obj().width() as f32;
+            let height = self.obj().height() as f32;
+            if width == 0.0 || height == 0.0 { return; }
+
+            let palette = self.palette.borrow();
+            let to_rgba = |color: colorous::Color, alpha: f32| RGBA::new(
+                color.r as f32 / 255.0,
+                color.g as f32 / 255.0,
+                color.b as f32 / 255.0,
+                alpha,
+            );
+            let (background, foreground) = (palette.background(), palette.foreground());
+            snapshot.append_color(&to_rgba(background, 1.0), &Rect::new(0.0, 0.0, width, height));
+
+            let traces = self.traces.borrow();
+            if traces.levels.is_empty() { return; }
+
+            let scale = self.frequency_scale.get();
+            let nyquist = self.fft.borrow().transform.sample_rate() / 2.0;
+            let range = frequency_scale::default_range(nyquist);
+            let (min_db, max_db) = (self.min_db.get(), self.max_db.get());
+            let to_y = |db: f32| height * (1.0 - ((db - min_db) / (max_db - min_db)).clamp(0.0, 1.0));
+            // The loudest point of a trace between two positions across the widget
+            let y_between = |trace: &[f32], x0: f32, x1: f32| {
+                let frequencies = (scale.unmap(x0 / width, range), scale.unmap(x1 / width, range));
+                level_between(trace, frequencies, nyquist).map(to_y).unwrap_or(height)
+            };
+            let trace_path = |trace: &[f32]| {
+                let builder = gsk::PathBuilder::new();
+                let steps = (width / LINE_STEP).ceil() as usize;
+                for i in 0..=steps {
+                    let x = (i as f32 * LINE_STEP).min(width);
+                    let y = y_between(trace, x - LINE_STEP / 2.0, x + LINE_STEP / 2.0);
+                    if i == 0 { builder.move_to(x, y) } else { builder.line_to(x, y) }
+                }
+                builder
+            };
+
+            // The max hold trace sits behind everything else, as a faint outline
+            if self.max_hold.get() {
+                snapshot.append_stroke(&trace_path(&traces.max_hold).to_path(), &gsk::Stroke::new(1.0), &to_rgba(foreground, 0.5));
+            }
+
+            match self.spectrum_style.get() {
+                SpectrumStyle::Line => {
+                    // The area under the line is filled in by a copy which is closed along the bottom,
+                    // so that only the line itself is outlined
+                    let area = trace_path(&traces.levels);
+                    area.line_to(width, height);
+                    area.line_to(0.0, height);
+                    area.close();
+                    snapshot.append_fill(&area.to_path(), gsk::FillRule::Winding, &to_rgba(foreground, 0.3));
+                    snapshot.append_stroke(&trace_path(&traces.levels).to_path(), &gsk::Stroke::new(1.5), &to_rgba(foreground, 1.0));
+                    if self.peak_hold.get() {
+                        snapshot.append_stroke(&trace_path(&traces.peaks).to_path(), &gsk::Stroke::new(1.0), &to_rgba(foreground, 0.7));
+                    }
+                }
+                SpectrumStyle::Bars => {
+                    let bar_width = (width / BAR_COUNT as f32 - BAR_SPACING).max(1.0);
+                    for i in 0..BAR_COUNT {
+                        let (x0, x1) = (width * i as f32 / BAR_COUNT as f32, width * (i + 1) as f32 / BAR_COUNT as f32);
+                        let y = y_between(&traces.levels, x0, x1);
+                        snapshot.append_color(&to_rgba(foreground, 1.0), &Rect::new(x0, y, bar_width, height - y));
+                        if self.peak_hold.get() {
+                            let peak_y = y_between(&traces.peaks, x0, x1).min(height - PEAK_MARKER_HEIGHT);
+                            snapshot.append_color(&to_rgba(foreground, 0.7), &Rect::new(x0, peak_y, bar_width, PEAK_MARKER_HEIGHT));
+                        }
+                    }
+                }
+            }
+
+            // Frequency runs across and level runs up, which is how a spectrogram is laid out when its history
+            // scrolls down; the levels stand in for its time axis
+            let orientation = SpectrogramOrientation::TopToBottom;
+            axes::draw_axes(
+                self.obj().upcast_ref(),
+                snapshot,
+                orientation,
+                &axes::frequency_ticks(scale, range, orientation, (width, height)),
+                &axes::level_ticks(min_db, max_db, height),
+                false,
+            );
         }
     }
 
-    // Trait shared by all widgets
-    impl WidgetImpl for SpectrumAnalyzer {}
+    impl VisualizerImpl for SpectrumAnalyzer {
+        fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+            self.fft.borrow_mut().input_stream = sample_stream;
+        }
+    }
+
+    impl SpectrumAnalyzer {
+        pub fn sample_rate(&self) -> u32 {
+            self.fft.borrow().transform.sample_rate() as u32
+        }
 
-    // Trait shared by all boxes
-    impl BoxImpl for SpectrumAnalyzer {}
+        pub fn set_sample_rate(&self, sample_rate: u32) {
+            self.fft.borrow_mut().transform = FastFourierTransform::new(
+                sample_rate as Frequency,
+                DEFAULT_WINDOW_PERIOD,
+            );
+            *self.traces.borrow_mut() = SpectrumTraces::default();
+        }
+
+        /// Folds every frame which arrived since the previous update into the traces.
+        pub fn update(&self) {
+            let mut fft = self.fft.borrow_mut();
+            // While paused, the traces hold still
+            if self.discard_input_if_paused(&mut fft.input_stream) {
+                return;
+            }
+
+            let period = fft.stride;
+            let mut traces = self.traces.borrow_mut();
+            for frame in fft.process() {
+                traces.push(&frame, period, self.averaging.get(), self.averaging_time.get(), self.peak_decay.get());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Period = 0.1;
+
+    /// A frame in which every bin has the given power, all in the left channel.
+    fn frame(bins: usize, power: f32) -> Vec<StereoMagnitude> {
+        vec![(power.sqrt(), 0.0); bins]
+    }
+
+    fn to_db(power: f32) -> f32 {
+        10.0 * (power + 1e-7).log10()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn linear_averaging_covers_the_averaging_time() {
+        let mut traces = SpectrumTraces::default();
+        // Three frames fit in 0.3 seconds, so the first one drops out when the fourth arrives
+        for power in [3.0, 6.0, 9.0, 12.0] {
+            traces.push(&frame(4, power), PERIOD, SpectrumAveraging::Linear, 0.3, 0.0);
+        }
+        assert_eq!(traces.window.len(), 3);
+        assert_near(traces.power[0], 9.0);
+        assert_near(traces.levels[0], to_db(9.0));
+    }
+
+    #[test]
+    fn exponential_averaging_fades_by_the_averaging_time() {
+        let mut traces = SpectrumTraces::default();
+        traces.push(&frame(4, 1.0), PERIOD, SpectrumAveraging::Exponential, 0.2, 0.0);
+        assert_near(traces.power[0], 1.0);
+        // Each period leaves e^(-period / averaging time) of the old average
+        traces.push(&frame(4, 0.0), PERIOD, SpectrumAveraging::Exponential, 0.2, 0.0);
+        assert_near(traces.power[0], (-0.5f32).exp());
+    }
+
+    #[test]
+    fn peaks_fall_by_the_decay_rate_each_period() {
+        let mut traces = SpectrumTraces::default();
+        traces.push(&frame(4, 1.0), PERIOD, SpectrumAveraging::None, 0.2, 20.0);
+        for expected in [-2.0, -4.0, -6.0] {
+            traces.push(&frame(4, 1e-3), PERIOD, SpectrumAveraging::None, 0.2, 20.0);
+            assert_near(traces.peaks[0], expected + to_db(1.0));
+        }
+        // Peaks stop falling once they reach the level, while the max hold stays put
+        for _ in 0..20 {
+            traces.push(&frame(4, 1e-3), PERIOD, SpectrumAveraging::None, 0.2, 20.0);
+        }
+        assert_near(traces.peaks[0], to_db(1e-3));
+        assert_near(traces.max_hold[0], to_db(1.0));
+
+        traces.reset_max_hold();
+        assert_eq!(traces.max_hold, traces.levels);
+    }
+
+    #[test]
+    fn a_new_bin_count_starts_over() {
+        let mut traces = SpectrumTraces::default();
+        for _ in 0..3 {
+            traces.push(&frame(4, 1.0), PERIOD, SpectrumAveraging::Linear, 0.3, 20.0);
+        }
+        traces.push(&frame(2, 1e-3), PERIOD, SpectrumAveraging::Linear, 0.3, 20.0);
+        assert_eq!(traces.window.len(), 1);
+        assert_eq!(traces.levels.len(), 2);
+        assert_eq!(traces.peaks, traces.levels);
+        assert_eq!(traces.max_hold, traces.levels);
+        assert_near(traces.levels[0], to_db(1e-3));
+    }
 }
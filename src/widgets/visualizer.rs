@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use gtk::{gdk, glib, graphene, prelude::*, subclass::prelude::*};
+use ringbuf::{HeapCons, traits::Consumer};
+
+use crate::colorscheme::ColorScheme;
+use crate::fourier::StereoMagnitude;
+use crate::output_paths::timestamped_path;
+
+glib::wrapper! {
+    /// A widget which draws a stream of samples, so that the app can treat all of them alike.
+    ///
+    /// Implementors provide the `sample-rate`, `palette` and `paused` properties.
+    pub struct Visualizer(ObjectInterface<iface::Visualizer>)
+        @requires gtk::Widget;
+}
+
+/// Chooses a new file in the user's pictures directory, named after the current time.
+pub fn default_snapshot_path() -> PathBuf {
+    timestamped_path(glib::UserDirectory::Pictures, "png")
+}
+
+/// Renders a widget as it currently appears on screen.
+pub fn render_widget(widget: &gtk::Widget) -> Option<gdk::Texture> {
+    let (width, height) = (widget.width(), widget.height());
+    if width == 0 || height == 0 { return None; }
+    let snapshot = gtk::Snapshot::new();
+    gtk::WidgetPaintable::new(Some(widget)).snapshot(&snapshot, width as f64, height as f64);
+    let node = snapshot.to_node()?;
+    let renderer = widget.native()?.renderer()?;
+    Some(renderer.render_texture(node, Some(&graphene::Rect::new(0.0, 0.0, width as f32, height as f32))))
+}
+
+pub trait VisualizerExt: IsA<Visualizer> + 'static {
+    /// Starts drawing samples from a new stream, replacing the old one.
+    fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+        let iface = self.interface::<Visualizer>().unwrap();
+        (iface.as_ref().attach_input)(self.upcast_ref(), sample_stream)
+    }
+
+    /// Renders the visualizer as it currently appears, or `None` if it isn't being shown.
+    fn export_snapshot(&self) -> Option<gdk::Texture> {
+        let iface = self.interface::<Visualizer>().unwrap();
+        (iface.as_ref().export_snapshot)(self.upcast_ref())
+    }
+
+    fn save_snapshot(&self, path: &Path) -> Result<(), glib::BoolError> {
+        let texture = self.export_snapshot()
+            .ok_or_else(|| glib::bool_error!("The visualizer isn't being shown"))?;
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory).ok();
+        }
+        texture.save_to_png(path)
+    }
+
+    fn sample_rate(&self) -> u32 { self.property("sample-rate") }
+
+    fn set_sample_rate(&self, sample_rate: u32) { self.set_property("sample-rate", sample_rate) }
+
+    fn palette(&self) -> ColorScheme { self.property("palette") }
+
+    fn set_palette(&self, palette: &ColorScheme) { self.set_property("palette", palette) }
+
+    /// While paused, the display stays as it was; incoming samples are discarded unless the visualizer has scrollback.
+    fn is_paused(&self) -> bool { self.property("paused") }
+
+    fn set_paused(&self, paused: bool) { self.set_property("paused", paused) }
+}
+
+impl<O: IsA<Visualizer>> VisualizerExt for O {}
+
+pub trait VisualizerImpl: WidgetImpl + ObjectSubclass<Type: IsA<Visualizer> + IsA<gtk::Widget>> {
+    fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>);
+
+    /// While paused, throws away everything waiting in the input so the subscription doesn't back up.
+    ///
+    /// Returns whether the visualizer is paused, in which case it should keep showing what it already has.
+    fn discard_input_if_paused(&self, input: &mut HeapCons<StereoMagnitude>) -> bool {
+        let paused = self.obj().is_paused();
+        if paused {
+            input.clear();
+        }
+        paused
+    }
+
+    fn export_snapshot(&self) -> Option<gdk::Texture> {
+        render_widget(self.obj().upcast_ref())
+    }
+}
+
+unsafe impl<T: VisualizerImpl> IsImplementable<T> for Visualizer {
+    fn interface_init(iface: &mut glib::Interface<Self>) {
+        let iface = iface.as_mut();
+        iface.attach_input = attach_input_trampoline::<T>;
+        iface.export_snapshot = export_snapshot_trampoline::<T>;
+    }
+}
+
+fn attach_input_trampoline<T: VisualizerImpl>(visualizer: &Visualizer, sample_stream: HeapCons<StereoMagnitude>) {
+    let visualizer = visualizer.dynamic_cast_ref::<T::Type>().unwrap();
+    visualizer.imp().attach_input(sample_stream)
+}
+
+fn export_snapshot_trampoline<T: VisualizerImpl>(visualizer: &Visualizer) -> Option<gdk::Texture> {
+    let visualizer = visualizer.dynamic_cast_ref::<T::Type>().unwrap();
+    visualizer.imp().export_snapshot()
+}
+
+mod iface {
+    use std::sync::OnceLock;
+    use glib::{gobject_ffi, ParamSpec, ParamSpecBoolean, ParamSpecObject, ParamSpecUInt};
+    use super::*;
+
+    #[derive(Copy, Clone)]
+    #[repr(C)]
+    pub struct VisualizerInterface {
+        parent: gobject_ffi::GTypeInterface,
+        pub(super) attach_input: fn(&super::Visualizer, HeapCons<StereoMagnitude>),
+        pub(super) export_snapshot: fn(&super::Visualizer) -> Option<gdk::Texture>,
+    }
+
+    unsafe impl InterfaceStruct for VisualizerInterface {
+        type Type = Visualizer;
+    }
+
+    pub enum Visualizer {}
+
+    #[glib::object_interface]
+    impl ObjectInterface for Visualizer {
+        const NAME: &'static str = "SpectrogramVisualizer";
+        type Prerequisites = (gtk::Widget,);
+        type Interface = VisualizerInterface;
+
+        fn interface_init(iface: &mut VisualizerInterface) {
+            iface.attach_input = |_, _| {};
+            iface.export_snapshot = |visualizer| render_widget(visualizer.upcast_ref());
+        }
+
+        fn properties() -> &'static [ParamSpec] {
+            static PROPERTIES: OnceLock<Vec<ParamSpec>> = OnceLock::new();
+            PROPERTIES.get_or_init(|| {
+                vec![
+                    ParamSpecUInt::builder("sample-rate").build(),
+                    ParamSpecObject::builder::<ColorScheme>("palette").build(),
+                    ParamSpecBoolean::builder("paused").build(),
+                ]
+            })
+        }
+    }
+}
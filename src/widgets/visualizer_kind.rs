@@ -0,0 +1,100 @@
+use gtk::{glib, prelude::*};
+
+use crate::devices::audio_input_list_model::AudioInputListModel;
+use crate::widgets::gpu_spectrogram::GPUSpectrogram;
+use crate::widgets::level_meter::LevelMeter;
+use crate::widgets::oscilloscope::Oscilloscope;
+use crate::widgets::placeholder::PlaceholderVisualizer;
+use crate::widgets::simple_spectrogram::SimpleSpectrogram;
+use crate::widgets::spectrum_analyzer::SpectrumAnalyzer;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt};
+use crate::widgets::waterfall_surface::WaterfallSurface;
+
+/// The visualizers which can be placed in a pane.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "SpectrogramVisualizerKind")]
+pub enum VisualizerKind {
+    #[default]
+    GpuSpectrogram,
+    SimpleSpectrogram,
+    Oscilloscope,
+    LevelMeter,
+    Waterfall3d,
+    Spectrum,
+    /// Shows how many samples arrive with each frame, which is handy for checking an input is flowing
+    Placeholder,
+}
+
+impl VisualizerKind {
+    pub const ALL: [VisualizerKind; 7] = [
+        VisualizerKind::GpuSpectrogram,
+        VisualizerKind::SimpleSpectrogram,
+        VisualizerKind::Oscilloscope,
+        VisualizerKind::LevelMeter,
+        VisualizerKind::Waterfall3d,
+        VisualizerKind::Spectrum,
+        VisualizerKind::Placeholder,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VisualizerKind::GpuSpectrogram => "Spectrogram",
+            VisualizerKind::SimpleSpectrogram => "Spectrogram (Software)",
+            VisualizerKind::Oscilloscope => "Oscilloscope",
+            VisualizerKind::LevelMeter => "Level Meter",
+            VisualizerKind::Waterfall3d => "3D Waterfall",
+            VisualizerKind::Spectrum => "Spectrum Analyzer",
+            VisualizerKind::Placeholder => "Sample Counter",
+        }
+    }
+
+    /// A stable name, used when saving layouts.
+    pub fn id(&self) -> &'static str {
+        match self {
+            VisualizerKind::GpuSpectrogram => "gpu-spectrogram",
+            VisualizerKind::SimpleSpectrogram => "simple-spectrogram",
+            VisualizerKind::Oscilloscope => "oscilloscope",
+            VisualizerKind::LevelMeter => "level-meter",
+            VisualizerKind::Waterfall3d => "waterfall-3d",
+            VisualizerKind::Spectrum => "spectrum",
+            VisualizerKind::Placeholder => "placeholder",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    /// The kind's position in [VisualizerKind::ALL], which is also the order they're listed in.
+    pub fn position(&self) -> u32 {
+        Self::ALL.iter().position(|kind| kind == self).unwrap() as u32
+    }
+
+    /// Creates a visualizer with its own subscription to the input, which follows the input's settings.
+    pub fn build(&self, input: &AudioInputListModel) -> Visualizer {
+        let visualizer: Visualizer = match self {
+            VisualizerKind::GpuSpectrogram => GPUSpectrogram::new(input.subscribe()).upcast(),
+            VisualizerKind::SimpleSpectrogram => SimpleSpectrogram::new(input.subscribe()).upcast(),
+            VisualizerKind::Oscilloscope => Oscilloscope::new(input.subscribe()).upcast(),
+            VisualizerKind::LevelMeter => LevelMeter::new(input.subscribe()).upcast(),
+            VisualizerKind::Waterfall3d => WaterfallSurface::new(input.subscribe()).upcast(),
+            VisualizerKind::Spectrum => SpectrumAnalyzer::new(input.subscribe()).upcast(),
+            VisualizerKind::Placeholder => PlaceholderVisualizer::new(input.subscribe()).upcast(),
+        };
+
+        // Transforms can't be planned for a rate of zero, so the rate is only copied once a source is running
+        input.bind_property("sample-rate", &visualizer, "sample-rate").build();
+        if input.sample_rate() > 0 {
+            visualizer.set_sample_rate(input.sample_rate());
+        }
+        if visualizer.find_property("discontinuities").is_some() {
+            input.bind_property("discontinuities", &visualizer, "discontinuities").build();
+        }
+        visualizer
+    }
+
+    /// Whether the visualizer renders with GL, and so benefits from being offloaded.
+    pub fn is_offloadable(&self) -> bool {
+        matches!(self, VisualizerKind::GpuSpectrogram | VisualizerKind::Oscilloscope | VisualizerKind::Waterfall3d)
+    }
+}
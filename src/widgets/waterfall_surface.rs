@@ -0,0 +1,462 @@
+use std::{cell::RefCell, rc::Rc};
+use std::cell::Cell;
+
+use gtk::{gdk, glib, prelude::*, subclass::prelude::*};
+
+use adw::glib::{clone, Properties, Object, ControlFlow::Continue, property::PropertySet};
+use adw::subclass::prelude::ObjectSubclassExt;
+
+use ringbuf::HeapCons;
+
+use glium::{index::PrimitiveType, program, uniform, Frame, Surface};
+
+use crate::colorscheme::{ColorScheme, DEFAULT_MAX_DB, DEFAULT_MIN_DB};
+use crate::fourier::StereoMagnitude;
+use crate::frequency_scale::{self, FrequencyScale};
+use crate::widgets::frame_ring::{self, FrameRing};
+use crate::widgets::glarea_backend::GLAreaBackend;
+use crate::widgets::visualizer::{Visualizer, VisualizerExt, VisualizerImpl};
+
+// The surface shows this much history, as a ring of frames like the GPU spectrogram's
+const HISTORY_SECONDS: f32 = 5.0;
+const HISTORY_FRAMES: usize = 256;
+// Vertices across frequency and along time; each cell between them is drawn as two triangles
+const GRID_COLUMNS: u32 = 256;
+const GRID_ROWS: u32 = HISTORY_FRAMES as u32;
+const HEIGHT_SCALE: f32 = 0.6;
+
+/// Where the camera sits, orbiting the middle of the surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    /// Rotation around the vertical axis, in radians; zero looks along the time axis from the newest frame
+    pub yaw: f32,
+    /// Elevation above the surface, in radians
+    pub pitch: f32,
+    pub distance: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self { yaw: 0.5, pitch: 0.45, distance: 3.2 }
+    }
+}
+
+impl Camera {
+    // The surface is only meant to be seen from above, so the camera stays over it
+    const MIN_PITCH: f32 = 0.05;
+    const MAX_PITCH: f32 = 1.5;
+    const MIN_DISTANCE: f32 = 1.5;
+    const MAX_DISTANCE: f32 = 10.0;
+
+    pub fn orbited(&self, yaw: f32, pitch: f32) -> Self {
+        Self {
+            yaw: (self.yaw + yaw).rem_euclid(std::f32::consts::TAU),
+            pitch: (self.pitch + pitch).clamp(Self::MIN_PITCH, Self::MAX_PITCH),
+            ..*self
+        }
+    }
+
+    pub fn zoomed(&self, factor: f32) -> Self {
+        Self {
+            distance: (self.distance * factor).clamp(Self::MIN_DISTANCE, Self::MAX_DISTANCE),
+            ..*self
+        }
+    }
+
+    /// The combined projection and view matrix, for a viewport with the given aspect ratio.
+    fn matrix(&self, aspect: f32) -> Matrix {
+        let target = [0.0, 0.2, 0.0];
+        let eye = [
+            target[0] + self.distance * self.pitch.cos() * self.yaw.sin(),
+            target[1] + self.distance * self.pitch.sin(),
+            target[2] + self.distance * self.pitch.cos() * self.yaw.cos(),
+        ];
+        multiply(&perspective(0.8, aspect, 0.1, 100.0), &look_at(eye, target, [0.0, 1.0, 0.0]))
+    }
+}
+
+// Matrices are stored as columns, which is how GLSL expects them
+type Matrix = [[f32; 4]; 4];
+type Vector = [f32; 3];
+
+fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Matrix {
+    let f = 1.0 / (fov_y / 2.0).tan();
+    [
+        [f / aspect, 0.0, 0.0, 0.0],
+        [0.0, f, 0.0, 0.0],
+        [0.0, 0.0, (far + near) / (near - far), -1.0],
+        [0.0, 0.0, 2.0 * far * near / (near - far), 0.0],
+    ]
+}
+
+fn look_at(eye: Vector, target: Vector, up: Vector) -> Matrix {
+    let forward = normalize([target[0] - eye[0], target[1] - eye[1], target[2] - eye[2]]);
+    let side = normalize(cross(forward, up));
+    let up = cross(side, forward);
+    [
+        [side[0], up[0], -forward[0], 0.0],
+        [side[1], up[1], -forward[1], 0.0],
+        [side[2], up[2], -forward[2], 0.0],
+        [-dot(side, eye), -dot(up, eye), dot(forward, eye), 1.0],
+    ]
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|column| std::array::from_fn(|row| (0..4).map(|k| a[k][row] * b[column][k]).sum()))
+}
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: Vector, b: Vector) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: Vector) -> Vector {
+    let length = dot(v, v).sqrt();
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+glib::wrapper! {
+    /// Recent frames drawn as a 3D surface, with height and color showing the level at each frequency and time.
+    pub struct WaterfallSurface(ObjectSubclass<imp::WaterfallSurface>)
+        @extends gtk::GLArea, gtk::Widget,
+        @implements Visualizer;
+}
+
+impl WaterfallSurface {
+    pub fn new(sample_stream: HeapCons<StereoMagnitude>) -> WaterfallSurface {
+        let object: WaterfallSurface = Object::builder().build();
+        object.attach_input(sample_stream);
+        add_camera_gestures(&object);
+        object.add_tick_callback(|surface, _| {
+            surface.queue_draw();
+            Continue
+        });
+        object
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.imp().camera.get()
+    }
+
+    pub fn set_camera(&self, camera: Camera) {
+        self.imp().camera.set(camera);
+        self.queue_draw();
+    }
+}
+
+fn add_camera_gestures(surface: &WaterfallSurface) {
+    // Dragging orbits the camera around the surface
+    let start_camera = Rc::new(Cell::new(surface.camera()));
+    let drag = gtk::GestureDrag::new();
+    drag.connect_drag_begin(clone!(
+        #[weak] surface,
+        #[strong] start_camera,
+        move |_, _, _| start_camera.set(surface.camera())
+    ));
+    drag.connect_drag_update(clone!(
+        #[weak] surface,
+        #[strong] start_camera,
+        move |_, dx, dy| {
+            let size = surface.width().min(surface.height()).max(1) as f32;
+            surface.set_camera(start_camera.get().orbited(-dx as f32 / size * 2.0, dy as f32 / size * 2.0));
+        }
+    ));
+    surface.add_controller(drag);
+
+    // Scrolling moves the camera closer or further away
+    let scroll = gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::VERTICAL);
+    scroll.connect_scroll(clone!(
+        #[weak] surface,
+        #[upgrade_or] glib::Propagation::Proceed,
+        move |_, _, dy| {
+            surface.set_camera(surface.camera().zoomed(1.1f32.powf(dy as f32)));
+            glib::Propagation::Stop
+        }
+    ));
+    surface.add_controller(scroll);
+
+    // Double-clicking goes back to the default view
+    let click = gtk::GestureClick::new();
+    click.connect_pressed(clone!(
+        #[weak] surface,
+        move |_, n_press, _, _| if n_press == 2 { surface.set_camera(Camera::default()) }
+    ));
+    surface.add_controller(click);
+}
+
+mod imp {
+    use glium::Rect;
+    use glium::texture::{MipmapsOption, Texture2d, UncompressedFloatFormat};
+    use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+    use super::*;
+
+    #[derive(Properties)]
+    #[properties(wrapper_type = super::WaterfallSurface)]
+    pub struct WaterfallSurface {
+        #[property(
+            name = "sample-rate", get = Self::sample_rate, set = Self::set_sample_rate, type = u32,
+            override_interface = Visualizer
+        )]
+        pub ring: RefCell<FrameRing>,
+
+        #[property(get, set = Self::set_palette, override_interface = Visualizer)]
+        pub palette: RefCell<ColorScheme>,
+
+        #[property(get, set, override_interface = Visualizer)]
+        pub paused: Cell<bool>,
+
+        /// How frequencies are spread from the left of the surface to the right
+        #[property(get, set, builder(FrequencyScale::default()))]
+        pub frequency_scale: Cell<FrequencyScale>,
+
+        /// The levels at the bottom and top of the surface, in decibels
+        #[property(get, set)]
+        pub min_db: Cell<f32>,
+        #[property(get, set)]
+        pub max_db: Cell<f32>,
+
+        pub camera: Cell<Camera>,
+
+        context: RefCell<Option<Rc<glium::backend::Context>>>,
+        program: RefCell<Option<glium::Program>>,
+        palette_texture: RefCell<Option<Texture2d>>,
+        /// The frequency under each column of the grid
+        frequency_texture: RefCell<Option<Texture2d>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for WaterfallSurface {
+        const NAME: &'static str = "WaterfallSurface";
+        type Type = super::WaterfallSurface;
+        type ParentType = gtk::GLArea;
+
+        fn new() -> Self {
+            let ring = FrameRing::new(HISTORY_SECONDS / HISTORY_FRAMES as f32, HISTORY_FRAMES, MipmapsOption::NoMipmap);
+            Self {
+                ring: ring.into(),
+                palette: ColorScheme::new_mono(colorous::MAGMA, "magma").into(),
+                paused: false.into(),
+                frequency_scale: FrequencyScale::default().into(),
+                min_db: DEFAULT_MIN_DB.into(),
+                max_db: DEFAULT_MAX_DB.into(),
+                camera: Camera::default().into(),
+                context: None.into(),
+                program: None.into(),
+                palette_texture: None.into(),
+                frequency_texture: None.into(),
+            }
+        }
+    }
+
+    #[glib::derived_properties]
+    impl ObjectImpl for WaterfallSurface {}
+
+    impl WidgetImpl for WaterfallSurface {
+        fn realize(&self) {
+            self.obj().set_required_version(3, 2);
+            self.obj().set_has_depth_buffer(true);
+
+            self.parent_realize();
+
+            let widget = self.obj();
+            if widget.error().is_some() {
+                return;
+            }
+            // glium looks for a depth buffer when its context is created, so the area's buffers have to be bound first
+            widget.make_current();
+            widget.attach_buffers();
+
+            // SAFETY: see GPUSpectrogram::realize; the context is destroyed in `unrealize()`
+            let context = unsafe {
+                let backend = GLAreaBackend::from(widget.clone().upcast::<gtk::GLArea>());
+                glium::backend::Context::new(backend, true, Default::default())
+            }.unwrap();
+
+            let program = program!(
+                &context,
+                150 => {
+                    vertex: "
+                        #version 150
+                        uniform uint columns;
+                        uniform uint rows;
+
+                        uniform uint num_samples;
+                        uniform uint offset;
+                        uniform float nyquist_frequency;
+                        uniform float min_db;
+                        uniform float max_db;
+                        uniform float height_scale;
+                        uniform mat4 matrix;
+                        uniform sampler2D fft;
+                        uniform sampler2D frequencies;
+
+                        out vec2 level;
+
+                        void main() {
+                            // Each cell of the grid is two triangles, numbered along each row starting from the oldest
+                            const ivec2 corners[6] = ivec2[6](
+                                ivec2(0, 0), ivec2(1, 0), ivec2(0, 1),
+                                ivec2(0, 1), ivec2(1, 0), ivec2(1, 1)
+                            );
+                            int cells_per_row = int(columns) - 1;
+                            int cell = gl_VertexID / 6;
+                            ivec2 vertex = ivec2(cell % cells_per_row, cell / cells_per_row) + corners[gl_VertexID % 6];
+                            vec2 position = vec2(vertex) / vec2(int(columns) - 1, int(rows) - 1);
+
+                            // Each column's frequency was placed by FrequencyScale, the same way as the spectrograms'
+                            float frequency = texelFetch(frequencies, ivec2(vertex.x, 0), 0).r;
+                            float time = (position.y * float(num_samples - 1u) + float(offset)) / float(num_samples);
+                            vec2 magnitude = textureLod(fft, vec2(frequency / nyquist_frequency, time), 0.0).rg;
+
+                            float magnitude_log = 10 * log(dot(magnitude, magnitude) + 1e-7) / log(10);
+                            float height = clamp((magnitude_log - min_db) / (max_db - min_db), 0.0, 1.0);
+                            float pan = magnitude.y / (magnitude.x + magnitude.y);
+                            level = vec2(pan, height);
+
+                            // Frequency runs from left to right, and time from the back (oldest) to the front
+                            gl_Position = matrix * vec4(2.0 * position.x - 1.0, height * height_scale, 2.0 * position.y - 1.0, 1.0);
+                        }
+                    ",
+                    fragment: "
+                        #version 150
+                        in vec2 level;
+                        uniform vec3 background;
+                        uniform sampler2D palette;
+                        out vec4 f_color;
+                        void main() {
+                            // Stereo palettes fade in with the level, so they're mixed with the background here
+                            vec4 color = texture(palette, level);
+                            f_color = vec4(mix(background, color.rgb, color.a), 1.0);
+                        }
+                    "
+                },
+            ).unwrap();
+
+            self.context.replace(Some(context));
+            self.program.replace(Some(program));
+        }
+
+        fn unrealize(&self) {
+            self.context.replace(None);
+            self.program.replace(None);
+            self.palette_texture.replace(None);
+            self.frequency_texture.replace(None);
+            self.ring.borrow_mut().reset();
+
+            self.parent_unrealize();
+        }
+    }
+
+    impl GLAreaImpl for WaterfallSurface {
+        fn render(&self, _context: &gdk::GLContext) -> glib::Propagation {
+            let context_binding = self.context.borrow();
+            let Some(context) = context_binding.as_ref() else { return glib::Propagation::Proceed };
+            let program_binding = self.program.borrow();
+            let program = program_binding.as_ref().unwrap();
+            let palette = self.palette.borrow();
+            let bg_color = palette.background();
+            let background = [bg_color.r as f32 / 255.0, bg_color.g as f32 / 255.0, bg_color.b as f32 / 255.0];
+            let mut ring = self.ring.borrow_mut();
+            let nyquist_frequency = ring.nyquist_frequency();
+            ring.prepare(context);
+
+            if self.palette_texture.borrow().is_none() {
+                self.palette_texture.set(frame_ring::palette_texture(context, &palette).into());
+            }
+            let palette_texture_binding = self.palette_texture.borrow();
+            let palette_texture = palette_texture_binding.as_ref().unwrap();
+
+            if self.frequency_texture.borrow().is_none() {
+                self.frequency_texture.set(Texture2d::empty_with_format(
+                    context,
+                    UncompressedFloatFormat::F32,
+                    MipmapsOption::NoMipmap,
+                    GRID_COLUMNS,
+                    1,
+                ).unwrap().into());
+            }
+            let frequency_texture_binding = self.frequency_texture.borrow();
+            let frequency_texture = frequency_texture_binding.as_ref().unwrap();
+            let frequency_range = frequency_scale::default_range(nyquist_frequency);
+            let frequencies: Vec<f32> = (0..GRID_COLUMNS)
+                .map(|column| self.frequency_scale.get().unmap(column as f32 / (GRID_COLUMNS - 1) as f32, frequency_range))
+                .collect();
+            frequency_texture.write(Rect { left: 0, bottom: 0, width: GRID_COLUMNS, height: 1 }, vec![frequencies]);
+
+            frame_ring::rebind_texture_units(context);
+
+            self.discard_input_if_paused(&mut ring.fft.input_stream);
+            ring.fill(|_, _| {});
+            let fft_texture = ring.texture();
+
+            let mut fft_sampler = fft_texture.sampled()
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .minify_filter(MinifySamplerFilter::Linear);
+            // Frequencies stop at the edge of the texture, while time wraps around the ring
+            fft_sampler.1.wrap_function = (SamplerWrapFunction::Clamp, SamplerWrapFunction::Repeat, SamplerWrapFunction::Repeat);
+            let palette_sampler = palette_texture.sampled()
+                .wrap_function(SamplerWrapFunction::Clamp)
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .minify_filter(MinifySamplerFilter::Linear);
+
+            let (width, height) = context.get_framebuffer_dimensions();
+            let camera = self.camera.get();
+            let mut frame = Frame::new(context.clone(), (width, height));
+            frame.clear_color_and_depth((background[0], background[1], background[2], 1.0), 1.0);
+            frame.draw(
+                glium::vertex::EmptyVertexAttributes { len: ((GRID_COLUMNS - 1) * (GRID_ROWS - 1) * 6) as usize },
+                &glium::index::NoIndices(PrimitiveType::TrianglesList),
+                program,
+                &uniform! {
+                    columns: GRID_COLUMNS,
+                    rows: GRID_ROWS,
+                    num_samples: fft_texture.height(),
+                    offset: ring.offset() as u32,
+                    nyquist_frequency: nyquist_frequency,
+                    min_db: self.min_db.get(),
+                    max_db: self.max_db.get(),
+                    height_scale: HEIGHT_SCALE,
+                    matrix: camera.matrix(width as f32 / height.max(1) as f32),
+                    fft: fft_sampler,
+                    frequencies: frequency_texture.sampled(),
+                    background: background,
+                    palette: palette_sampler,
+                },
+                &glium::DrawParameters {
+                    depth: glium::Depth {
+                        test: glium::DepthTest::IfLess,
+                        write: true,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            ).unwrap();
+            frame.finish().unwrap();
+            glib::Propagation::Proceed
+        }
+    }
+
+    impl VisualizerImpl for WaterfallSurface {
+        fn attach_input(&self, sample_stream: HeapCons<StereoMagnitude>) {
+            self.ring.borrow_mut().fft.input_stream = sample_stream;
+        }
+    }
+
+    impl WaterfallSurface {
+        pub fn sample_rate(&self) -> u32 {
+            self.ring.borrow().sample_rate()
+        }
+
+        pub fn set_sample_rate(&self, sample_rate: u32) {
+            self.ring.borrow_mut().set_sample_rate(sample_rate);
+        }
+
+        pub fn set_palette(&self, palette: ColorScheme) {
+            self.palette.set(palette);
+            self.palette_texture.set(None);
+        }
+    }
+}